@@ -0,0 +1,22 @@
+# aia shell integration for bash, load it with:
+#   eval "$(aia shell-init bash)"
+# Ctrl-G sends the command line to aia and replaces it with the suggested command.
+
+__aia_widget() {
+    [ -z "$READLINE_LINE" ] && return
+    local output exit_status
+    output=$(aia --non-interactive --shell bash -- "$READLINE_LINE" </dev/null)
+    exit_status=$?
+    case $exit_status in
+        0)
+            READLINE_LINE=$output
+            READLINE_POINT=${#READLINE_LINE}
+            ;;
+        3 | 4)
+            # An answer or a question: show it and keep the command line
+            printf '%s\n' "$output"
+            ;;
+    esac
+}
+
+bind -x '"\C-g": __aia_widget'
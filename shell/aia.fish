@@ -0,0 +1,21 @@
+# aia shell integration for fish, load it with:
+#   aia shell-init fish | source
+# Ctrl-G sends the command line to aia and replaces it with the suggested command.
+
+function __aia_widget
+    set -l line (commandline | string collect)
+    test -z "$line"; and return
+    set -l output (aia --non-interactive --shell fish -- "$line" </dev/null)
+    set -l exit_status $status
+    switch $exit_status
+        case 0
+            commandline --replace -- (string join \n -- $output)
+        case 3 4
+            # An answer or a question: show it and keep the command line
+            echo
+            string join \n -- $output
+    end
+    commandline --function repaint
+end
+
+bind \cg __aia_widget
@@ -0,0 +1,36 @@
+use crate::bash::{Connector, Script};
+use crate::shell::ShellKind;
+
+// Instructions added to the conversation when agent mode is enabled
+pub const AGENT_MESSAGE: &str = "Agent mode is enabled: you may reach the user's goal in several steps. \
+Propose one command at a time; after each executed command you will receive its exit status and output. \
+Keep proposing commands until the goal is reached, then reply with an `answer` summarizing the result.";
+
+// Checks whether every program in a command starts with one of the auto-approved prefixes.
+// Redirections, substitutions and commands for non-POSIX shells are never auto-approved.
+pub fn is_auto_approved(command: &str, shell: ShellKind, auto_approve: &[String]) -> bool {
+    if !shell.is_posix() {
+        return false;
+    }
+    let Ok(script) = Script::parse(command) else {
+        return false;
+    };
+    if !script.substitutions.is_empty() || script.commands.is_empty() {
+        return false;
+    }
+
+    script.commands.iter().all(|command| {
+        let words = command
+            .words
+            .iter()
+            .map(|word| word.text.as_str())
+            .collect::<Vec<_>>();
+        command.assignments.is_empty()
+            && command.redirects.is_empty()
+            && command.connector != Some(Connector::Background)
+            && auto_approve.iter().any(|prefix| {
+                let prefix = prefix.split_whitespace().collect::<Vec<_>>();
+                words.starts_with(&prefix)
+            })
+    })
+}
@@ -0,0 +1,102 @@
+mod anthropic;
+pub mod ollama;
+mod openai;
+
+use std::{future::Future, pin::Pin};
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::config::{Config, Provider};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// The author of a conversation message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+// A provider-independent conversation message
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::System,
+            content: content.into(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+        }
+    }
+}
+
+// Token counts reported by the provider for a single request
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+// The raw reply of a backend
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub content: String,
+    // Whether the content is a JSON object constrained by `response_schema`
+    pub structured: bool,
+    pub usage: Option<Usage>,
+}
+
+pub const RESPONSE_SCHEMA_NAME: &str = "aia_response";
+pub const RESPONSE_SCHEMA_DESCRIPTION: &str =
+    "The assistant's reasoning and its command, question or answer for the user";
+
+// JSON schema of a structured reply; fields that do not apply to the type are null
+pub fn response_schema() -> serde_json::Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "thought": { "type": "string" },
+            "type": { "type": "string", "enum": ["command", "question", "answer"] },
+            "command": { "type": ["string", "null"] },
+            "question": { "type": ["string", "null"] },
+            "answer": { "type": ["string", "null"] }
+        },
+        "required": ["thought", "type", "command", "question", "answer"],
+        "additionalProperties": false
+    })
+}
+
+// A chat completion provider
+pub trait Backend: Send + Sync {
+    // Sends the conversation and returns the model's reply
+    fn complete<'a>(&'a self, messages: &'a [Message]) -> BoxFuture<'a, Result<Completion>>;
+}
+
+// Creates the backend selected in the configuration
+pub fn from_config(config: &Config) -> Result<Box<dyn Backend>> {
+    match config.provider {
+        Provider::OpenAI => openai::new(config),
+        Provider::Ollama => Ok(Box::new(ollama::OllamaBackend::new(config)?)),
+        Provider::Anthropic => Ok(Box::new(anthropic::AnthropicBackend::new(config))),
+    }
+}
@@ -0,0 +1,383 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Backend, BoxFuture, Completion, Message, RESPONSE_SCHEMA_DESCRIPTION, RESPONSE_SCHEMA_NAME,
+    Role, Usage, response_schema,
+};
+use crate::config::Config;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+pub struct AnthropicBackend {
+    client: reqwest::Client,
+    api_base: String,
+    token: String,
+    model: String,
+    max_tokens: u32,
+}
+
+#[derive(Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<RequestMessage<'a>>,
+    tools: Vec<Tool>,
+    tool_choice: ToolChoice,
+}
+
+#[derive(Serialize)]
+struct Tool {
+    name: &'static str,
+    description: &'static str,
+    input_schema: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct ToolChoice {
+    r#type: &'static str,
+    name: &'static str,
+}
+
+#[derive(Serialize)]
+struct RequestMessage<'a> {
+    role: &'a str,
+    content: Vec<RequestContent<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RequestContent<'a> {
+    Text { text: &'a str },
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    content: Vec<ResponseContent>,
+    usage: Option<ResponseUsage>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseContent {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        input: serde_json::Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct ResponseUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    message: String,
+}
+
+impl AnthropicBackend {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_base: config.anthropic_api_base.trim_end_matches('/').to_string(),
+            token: config.anthropic_token.clone(),
+            model: config.anthropic_model.clone(),
+            max_tokens: config.anthropic_max_tokens,
+        }
+    }
+}
+
+// Splits off the system prompt and merges consecutive messages of the same role,
+// since the Messages API expects strictly alternating user and assistant turns.
+// The reply is forced through a tool whose input schema is the response schema.
+fn to_request<'a>(model: &'a str, max_tokens: u32, messages: &'a [Message]) -> MessagesRequest<'a> {
+    let system = messages
+        .iter()
+        .filter(|message| message.role == Role::System)
+        .map(|message| message.content.as_str())
+        .collect::<Vec<_>>();
+
+    let mut request_messages: Vec<RequestMessage> = Vec::new();
+    for message in messages {
+        let role = match message.role {
+            Role::System => continue,
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        let content = RequestContent::Text {
+            text: &message.content,
+        };
+        match request_messages.last_mut() {
+            Some(last) if last.role == role => last.content.push(content),
+            _ => request_messages.push(RequestMessage {
+                role,
+                content: vec![content],
+            }),
+        }
+    }
+
+    MessagesRequest {
+        model,
+        max_tokens,
+        system: (!system.is_empty()).then(|| system.join("\n\n")),
+        messages: request_messages,
+        tools: vec![Tool {
+            name: RESPONSE_SCHEMA_NAME,
+            description: RESPONSE_SCHEMA_DESCRIPTION,
+            input_schema: response_schema(),
+        }],
+        tool_choice: ToolChoice {
+            r#type: "tool",
+            name: RESPONSE_SCHEMA_NAME,
+        },
+    }
+}
+
+// Takes the reply from the forced tool call, or from the text if the model answered without it
+fn to_completion(response: MessagesResponse) -> Completion {
+    let mut text = String::new();
+    let mut tool_input = None;
+    for block in response.content {
+        match block {
+            ResponseContent::Text { text: block_text } => text.push_str(&block_text),
+            ResponseContent::ToolUse { input } => tool_input = Some(input.to_string()),
+            ResponseContent::Other => {}
+        }
+    }
+
+    let usage = response.usage.map(|usage| Usage {
+        prompt_tokens: usage.input_tokens,
+        completion_tokens: usage.output_tokens,
+    });
+
+    match tool_input {
+        Some(content) => Completion {
+            content,
+            structured: true,
+            usage,
+        },
+        None => Completion {
+            content: text,
+            structured: false,
+            usage,
+        },
+    }
+}
+
+impl Backend for AnthropicBackend {
+    fn complete<'a>(&'a self, messages: &'a [Message]) -> BoxFuture<'a, Result<Completion>> {
+        Box::pin(async move {
+            let request = to_request(&self.model, self.max_tokens, messages);
+
+            let response = self
+                .client
+                .post(format!("{}/v1/messages", self.api_base))
+                .header("x-api-key", &self.token)
+                .header("anthropic-version", ANTHROPIC_VERSION)
+                .json(&request)
+                .send()
+                .await
+                .with_context(|| format!("Failed to connect to {}", self.api_base))?;
+
+            let status = response.status();
+            let body = response
+                .text()
+                .await
+                .context("Failed to read Anthropic response")?;
+            if !status.is_success() {
+                let message = serde_json::from_str::<ErrorResponse>(&body)
+                    .map(|error| error.error.message)
+                    .unwrap_or(body);
+                return Err(anyhow!("Anthropic API error ({}): {}", status, message));
+            }
+
+            let response = serde_json::from_str::<MessagesResponse>(&body)
+                .context("Failed to parse Anthropic response")?;
+
+            Ok(to_completion(response))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const TOOL_USE_RESPONSE: &str = r#"{
+        "id": "msg_01",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-3-5-haiku-latest",
+        "content": [
+            {"type": "text", "text": "Listing the files."},
+            {
+                "type": "tool_use",
+                "id": "toolu_01",
+                "name": "aia_response",
+                "input": {"thought": "List files", "type": "command", "command": "ls", "question": null, "answer": null}
+            }
+        ],
+        "stop_reason": "tool_use",
+        "usage": {"input_tokens": 412, "output_tokens": 38}
+    }"#;
+
+    #[test]
+    fn request_splits_system_merges_roles_and_forces_the_tool() {
+        let messages = [
+            Message::system("You are aia"),
+            Message::system("Context"),
+            Message::user("list files"),
+            Message::user("in this directory"),
+            Message::assistant("{}"),
+            Message::user("thanks"),
+        ];
+        let request = serde_json::to_value(to_request("model", 1024, &messages)).unwrap();
+
+        assert_eq!(request["system"], "You are aia\n\nContext");
+        assert_eq!(
+            request["messages"],
+            json!([
+                {"role": "user", "content": [
+                    {"type": "text", "text": "list files"},
+                    {"type": "text", "text": "in this directory"}
+                ]},
+                {"role": "assistant", "content": [{"type": "text", "text": "{}"}]},
+                {"role": "user", "content": [{"type": "text", "text": "thanks"}]}
+            ])
+        );
+        assert_eq!(request["tools"][0]["name"], RESPONSE_SCHEMA_NAME);
+        assert_eq!(request["tools"][0]["input_schema"], response_schema());
+        assert_eq!(
+            request["tool_choice"],
+            json!({"type": "tool", "name": RESPONSE_SCHEMA_NAME})
+        );
+    }
+
+    #[test]
+    fn request_without_system_messages_omits_system() {
+        let messages = [Message::user("hi")];
+        let request = serde_json::to_value(to_request("model", 1024, &messages)).unwrap();
+        assert!(request.get("system").is_none());
+    }
+
+    #[test]
+    fn tool_use_response_is_structured() {
+        let response = serde_json::from_str::<MessagesResponse>(TOOL_USE_RESPONSE).unwrap();
+        let completion = to_completion(response);
+        assert!(completion.structured);
+        let content = serde_json::from_str::<serde_json::Value>(&completion.content).unwrap();
+        assert_eq!(content["command"], "ls");
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (412, 38));
+    }
+
+    #[test]
+    fn text_response_is_unstructured() {
+        let response = serde_json::from_str::<MessagesResponse>(
+            r#"{"content": [{"type": "text", "text": "[JSON]"}, {"type": "thinking"}]}"#,
+        )
+        .unwrap();
+        let completion = to_completion(response);
+        assert!(!completion.structured);
+        assert_eq!(completion.content, "[JSON]");
+        assert!(completion.usage.is_none());
+    }
+
+    // Serves a single canned HTTP response. Returns the server address and a task that
+    // resolves to the request it received.
+    async fn serve_once(
+        status: &'static str,
+        body: &'static str,
+    ) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0; 4096];
+            // Read the headers, then as much of the body as Content-Length announces
+            loop {
+                let read = socket.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                    let length = headers
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|value| value.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+                if read == 0 {
+                    break;
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (address, server)
+    }
+
+    fn backend(api_base: String) -> AnthropicBackend {
+        let mut config = toml::from_str::<Config>("").unwrap();
+        config.anthropic_api_base = api_base;
+        config.anthropic_token = "test-key".to_string();
+        AnthropicBackend::new(&config)
+    }
+
+    #[tokio::test]
+    async fn completes_against_a_mock_server() {
+        let (address, server) = serve_once("200 OK", TOOL_USE_RESPONSE).await;
+        let completion = backend(address)
+            .complete(&[Message::system("You are aia"), Message::user("list files")])
+            .await
+            .unwrap();
+        assert!(completion.structured);
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /v1/messages "));
+        assert!(request.contains("x-api-key: test-key"));
+        assert!(request.contains(&format!("anthropic-version: {}", ANTHROPIC_VERSION)));
+    }
+
+    #[tokio::test]
+    async fn reports_api_errors() {
+        let (address, server) = serve_once(
+            "401 Unauthorized",
+            r#"{"type": "error", "error": {"type": "authentication_error", "message": "invalid x-api-key"}}"#,
+        )
+        .await;
+        let err = backend(address)
+            .complete(&[Message::user("hi")])
+            .await
+            .unwrap_err();
+        server.await.unwrap();
+        assert!(format!("{:#}", err).contains("invalid x-api-key"));
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use super::{Backend, BoxFuture, Completion, Message, Role, Usage, response_schema};
+use crate::config::Config;
+
+pub struct OllamaBackend {
+    client: reqwest::Client,
+    url: String,
+    model: String,
+    structured_output: bool,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage<'a>>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    message: ResponseMessage,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct TagsResponse {
+    models: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    name: String,
+}
+
+impl OllamaBackend {
+    pub fn new(config: &Config) -> Result<Self> {
+        if config.ollama_model.is_empty() {
+            return Err(anyhow!("No Ollama model configured"));
+        }
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url: config.ollama_url.trim_end_matches('/').to_string(),
+            model: config.ollama_model.clone(),
+            structured_output: config.structured_output,
+        })
+    }
+}
+
+// Lists the models installed on the Ollama server
+pub async fn list_models(url: &str) -> Result<Vec<String>> {
+    let response = reqwest::get(format!("{}/api/tags", url.trim_end_matches('/')))
+        .await
+        .with_context(|| format!("Failed to connect to Ollama at {}", url))?
+        .error_for_status()
+        .context("Ollama returned an error while listing models")?
+        .json::<TagsResponse>()
+        .await
+        .context("Failed to parse Ollama model list")?;
+
+    Ok(response
+        .models
+        .into_iter()
+        .map(|model| model.name)
+        .collect())
+}
+
+impl Backend for OllamaBackend {
+    fn complete<'a>(&'a self, messages: &'a [Message]) -> BoxFuture<'a, Result<Completion>> {
+        Box::pin(async move {
+            let request = ChatRequest {
+                model: &self.model,
+                messages: messages
+                    .iter()
+                    .map(|message| ChatMessage {
+                        role: match message.role {
+                            Role::System => "system",
+                            Role::User => "user",
+                            Role::Assistant => "assistant",
+                        },
+                        content: &message.content,
+                    })
+                    .collect(),
+                stream: false,
+                format: self.structured_output.then(response_schema),
+            };
+
+            let response = self
+                .client
+                .post(format!("{}/api/chat", self.url))
+                .json(&request)
+                .send()
+                .await
+                .with_context(|| format!("Failed to connect to Ollama at {}", self.url))?
+                .error_for_status()
+                .context("Ollama returned an error")?
+                .json::<ChatResponse>()
+                .await
+                .context("Failed to parse Ollama response")?;
+
+            let usage = match (response.prompt_eval_count, response.eval_count) {
+                (None, None) => None,
+                (prompt_tokens, completion_tokens) => Some(Usage {
+                    prompt_tokens: prompt_tokens.unwrap_or_default(),
+                    completion_tokens: completion_tokens.unwrap_or_default(),
+                }),
+            };
+
+            Ok(Completion {
+                content: response.message.content,
+                structured: self.structured_output,
+                usage,
+            })
+        })
+    }
+}
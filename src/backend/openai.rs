@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result, anyhow};
+use async_openai::{
+    Client,
+    config::{AzureConfig, OpenAIConfig},
+    types::{
+        ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+        ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
+        CreateChatCompletionRequestArgs, ResponseFormat, ResponseFormatJsonSchema,
+    },
+};
+
+use super::{
+    Backend, BoxFuture, Completion, Message, RESPONSE_SCHEMA_DESCRIPTION, RESPONSE_SCHEMA_NAME,
+    Role, Usage, response_schema,
+};
+use crate::config::Config;
+
+pub struct OpenAIBackend<C: async_openai::config::Config> {
+    client: Client<C>,
+    model: String,
+    structured_output: bool,
+}
+
+// Creates a backend for the OpenAI API or any OpenAI-compatible server
+pub fn new(config: &Config) -> Result<Box<dyn Backend>> {
+    let http_client = build_http_client(&config.openai_headers)?;
+
+    if let Some(deployment) = &config.azure_deployment {
+        let api_base = config
+            .openai_api_base
+            .as_deref()
+            .context("Azure deployments require openai_api_base to be set")?;
+        let azure_config = AzureConfig::new()
+            .with_api_base(api_base)
+            .with_api_key(&config.openai_token)
+            .with_deployment_id(deployment)
+            .with_api_version(&config.azure_api_version);
+        return Ok(Box::new(OpenAIBackend {
+            client: Client::with_config(azure_config).with_http_client(http_client),
+            model: config.openai_model.clone(),
+            structured_output: config.structured_output,
+        }));
+    }
+
+    let mut openai_config = OpenAIConfig::new().with_api_key(&config.openai_token);
+    if let Some(api_base) = &config.openai_api_base {
+        openai_config = openai_config.with_api_base(api_base.trim_end_matches('/'));
+    }
+    if let Some(organization) = &config.openai_organization {
+        openai_config = openai_config.with_org_id(organization);
+    }
+    if let Some(project) = &config.openai_project {
+        openai_config = openai_config.with_project_id(project);
+    }
+
+    Ok(Box::new(OpenAIBackend {
+        client: Client::with_config(openai_config).with_http_client(http_client),
+        model: config.openai_model.clone(),
+        structured_output: config.structured_output,
+    }))
+}
+
+// Builds an HTTP client that sends the configured extra headers with every request
+fn build_http_client(headers: &HashMap<String, String>) -> Result<reqwest::Client> {
+    let mut header_map = reqwest::header::HeaderMap::new();
+    for (name, value) in headers {
+        let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid header name: {}", name))?;
+        let value = reqwest::header::HeaderValue::from_str(value)
+            .with_context(|| format!("Invalid value for header {}", name))?;
+        header_map.insert(name, value);
+    }
+
+    reqwest::Client::builder()
+        .default_headers(header_map)
+        .build()
+        .context("Failed to build HTTP client")
+}
+
+// Converts a conversation message into the OpenAI request format
+fn to_request_message(message: &Message) -> Result<ChatCompletionRequestMessage> {
+    let message = match message.role {
+        Role::System => ChatCompletionRequestSystemMessageArgs::default()
+            .content(message.content.as_str())
+            .build()
+            .context("Failed to build system message")?
+            .into(),
+        Role::User => ChatCompletionRequestUserMessageArgs::default()
+            .content(message.content.as_str())
+            .build()
+            .context("Failed to build user message")?
+            .into(),
+        Role::Assistant => ChatCompletionRequestAssistantMessageArgs::default()
+            .content(message.content.as_str())
+            .build()
+            .context("Failed to build assistant message")?
+            .into(),
+    };
+    Ok(message)
+}
+
+impl<C: async_openai::config::Config + Send + Sync> Backend for OpenAIBackend<C> {
+    fn complete<'a>(&'a self, messages: &'a [Message]) -> BoxFuture<'a, Result<Completion>> {
+        Box::pin(async move {
+            let messages = messages
+                .iter()
+                .map(to_request_message)
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut request_args = CreateChatCompletionRequestArgs::default();
+            request_args.model(&self.model).messages(messages);
+            if self.structured_output {
+                request_args.response_format(ResponseFormat::JsonSchema {
+                    json_schema: ResponseFormatJsonSchema {
+                        description: Some(RESPONSE_SCHEMA_DESCRIPTION.to_string()),
+                        name: RESPONSE_SCHEMA_NAME.to_string(),
+                        schema: Some(response_schema()),
+                        strict: Some(true),
+                    },
+                });
+            }
+            let request = request_args.build().context("Failed to create request")?;
+
+            let response = self
+                .client
+                .chat()
+                .create(request)
+                .await
+                .context("Failed to get OpenAI response")?;
+
+            let choice = response
+                .choices
+                .first()
+                .ok_or_else(|| anyhow!("No choices returned in response"))?;
+            let content = choice
+                .message
+                .content
+                .clone()
+                .ok_or_else(|| anyhow!("Failed to get response content"))?;
+            let usage = response.usage.map(|usage| Usage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            });
+
+            Ok(Completion {
+                content,
+                structured: self.structured_output,
+                usage,
+            })
+        })
+    }
+}
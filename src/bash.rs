@@ -0,0 +1,628 @@
+use anyhow::{Result, anyhow};
+
+// A word after quote removal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub text: String,
+    // Whether any part of the word was quoted or escaped
+    pub quoted: bool,
+    // Whether the word contains unquoted glob characters (*, ? or [)
+    pub glob: bool,
+    // Whether the word contains parameter expansions or substitutions
+    pub expansion: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKind {
+    // `<`
+    Input,
+    // `>` and `>|`
+    Output,
+    // `>>`
+    Append,
+    // `&>` and `&>>`, redirecting both stdout and stderr
+    OutputAndError { append: bool },
+    // `<&` and `>&`, duplicating a file descriptor
+    Duplicate,
+    // `<<` and `<<-`
+    HereDoc,
+    // `<<<`
+    HereString,
+    // `<>`
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub fd: Option<u32>,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+impl Redirect {
+    // Whether the redirection writes to its target file
+    pub fn writes_file(&self) -> bool {
+        matches!(
+            self.kind,
+            RedirectKind::Output
+                | RedirectKind::Append
+                | RedirectKind::OutputAndError { .. }
+                | RedirectKind::ReadWrite
+        )
+    }
+
+    // Whether the redirection replaces the contents of its target file
+    pub fn truncates_file(&self) -> bool {
+        matches!(
+            self.kind,
+            RedirectKind::Output | RedirectKind::OutputAndError { append: false }
+        )
+    }
+}
+
+// How a command is connected to the one that follows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    // `|` and `|&`
+    Pipe,
+    // `&&`
+    And,
+    // `||`
+    Or,
+    // `;` or a newline
+    Sequence,
+    // `&`
+    Background,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    // Leading `NAME=value` assignments
+    pub assignments: Vec<Word>,
+    // The program followed by its arguments
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+    pub connector: Option<Connector>,
+}
+
+impl SimpleCommand {
+    // The program name without any leading directory
+    pub fn program(&self) -> Option<&str> {
+        self.words
+            .first()
+            .map(|word| word.text.rsplit('/').next().unwrap_or(&word.text))
+    }
+
+    pub fn args(&self) -> &[Word] {
+        self.words.get(1..).unwrap_or_default()
+    }
+}
+
+// A parsed command line, flattened into its simple commands
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    pub commands: Vec<SimpleCommand>,
+    // Bodies of `$(...)` and backtick command substitutions, in order of appearance
+    pub substitutions: Vec<String>,
+}
+
+// Reserved words that introduce or close compound commands; the command follows them
+const RESERVED_WORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "do", "done", "while", "until", "!", "{", "}", "time",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Word(Word),
+    Operator(&'static str),
+    // A redirection operator with an optional file descriptor prefix
+    Redirect(Option<u32>, &'static str),
+}
+
+const OPERATORS: &[&str] = &[
+    "&>>", "<<<", "<<-", ";;", "||", "|&", "&&", "&>", "<<", "<>", "<&", ">>", ">&", ">|", "|",
+    "&", ";", "(", ")", "<", ">", "\n",
+];
+
+fn is_redirect(operator: &str) -> bool {
+    operator.contains(['<', '>'])
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    substitutions: Vec<String>,
+    // Delimiters of here-documents whose bodies start on the next line
+    pending_heredocs: Vec<(String, bool)>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            chars: input.chars().peekable(),
+            substitutions: Vec::new(),
+            pending_heredocs: Vec::new(),
+        }
+    }
+
+    fn rest_starts_with(&self, text: &str) -> bool {
+        let mut chars = self.chars.clone();
+        text.chars().all(|expected| chars.next() == Some(expected))
+    }
+
+    fn tokens(&mut self) -> Result<Vec<Token>> {
+        let mut tokens = Vec::new();
+        while let Some(&c) = self.chars.peek() {
+            if c == ' ' || c == '\t' {
+                self.chars.next();
+            } else if c == '\\' && self.rest_starts_with("\\\n") {
+                self.chars.next();
+                self.chars.next();
+            } else if c == '#' {
+                while self.chars.next_if(|&c| c != '\n').is_some() {}
+            } else if let Some(operator) = OPERATORS.iter().find(|op| self.rest_starts_with(op)) {
+                for _ in 0..operator.chars().count() {
+                    self.chars.next();
+                }
+                if *operator == "\n" {
+                    self.read_heredoc_bodies();
+                }
+                tokens.push(if is_redirect(operator) {
+                    Token::Redirect(None, operator)
+                } else {
+                    Token::Operator(operator)
+                });
+            } else {
+                let word = self.word()?;
+                // A word of digits directly followed by a redirection is its file descriptor
+                let redirect = OPERATORS
+                    .iter()
+                    .find(|op| is_redirect(op) && self.rest_starts_with(op));
+                match (redirect, word.text.parse::<u32>()) {
+                    (Some(operator), Ok(fd)) if !word.quoted => {
+                        for _ in 0..operator.chars().count() {
+                            self.chars.next();
+                        }
+                        tokens.push(Token::Redirect(Some(fd), operator));
+                    }
+                    _ => {
+                        if let Some(Token::Redirect(_, operator @ ("<<" | "<<-"))) = tokens.last() {
+                            self.pending_heredocs
+                                .push((word.text.clone(), *operator == "<<-"));
+                        }
+                        tokens.push(Token::Word(word));
+                    }
+                }
+            }
+        }
+        Ok(tokens)
+    }
+
+    // Skips the bodies of here-documents, which start after the line containing `<<`
+    fn read_heredoc_bodies(&mut self) {
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.pending_heredocs) {
+            loop {
+                let mut line = String::new();
+                while let Some(c) = self.chars.next_if(|&c| c != '\n') {
+                    line.push(c);
+                }
+                let at_end = self.chars.next().is_none();
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    &line
+                };
+                if line == delimiter || at_end {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn word(&mut self) -> Result<Word> {
+        let mut word = Word {
+            text: String::new(),
+            quoted: false,
+            glob: false,
+            expansion: false,
+        };
+
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                ' ' | '\t' => break,
+                _ if OPERATORS.iter().any(|op| self.rest_starts_with(op)) => break,
+                '\'' => {
+                    self.chars.next();
+                    word.quoted = true;
+                    loop {
+                        match self.chars.next() {
+                            Some('\'') => break,
+                            Some(c) => word.text.push(c),
+                            None => return Err(anyhow!("Unterminated single quote")),
+                        }
+                    }
+                }
+                '"' => {
+                    self.chars.next();
+                    word.quoted = true;
+                    self.double_quoted(&mut word)?;
+                }
+                '\\' => {
+                    self.chars.next();
+                    word.quoted = true;
+                    match self.chars.next() {
+                        Some('\n') | None => {}
+                        Some(c) => word.text.push(c),
+                    }
+                }
+                '$' | '`' => {
+                    word.expansion = true;
+                    self.expansion(&mut word)?;
+                }
+                '*' | '?' | '[' => {
+                    self.chars.next();
+                    word.glob = true;
+                    word.text.push(c);
+                }
+                _ => {
+                    self.chars.next();
+                    word.text.push(c);
+                }
+            }
+        }
+        Ok(word)
+    }
+
+    fn double_quoted(&mut self, word: &mut Word) -> Result<()> {
+        loop {
+            match self.chars.peek() {
+                Some('"') => {
+                    self.chars.next();
+                    return Ok(());
+                }
+                Some('\\') => {
+                    self.chars.next();
+                    match self.chars.next() {
+                        Some(c @ ('"' | '\\' | '$' | '`')) => word.text.push(c),
+                        Some('\n') => {}
+                        Some(c) => {
+                            word.text.push('\\');
+                            word.text.push(c);
+                        }
+                        None => return Err(anyhow!("Unterminated double quote")),
+                    }
+                }
+                Some('$' | '`') => {
+                    word.expansion = true;
+                    self.expansion(word)?;
+                }
+                Some(&c) => {
+                    self.chars.next();
+                    word.text.push(c);
+                }
+                None => return Err(anyhow!("Unterminated double quote")),
+            }
+        }
+    }
+
+    // Reads a `$name`, `${...}`, `$(...)`, `$((...))` or backtick expansion into the word verbatim
+    fn expansion(&mut self, word: &mut Word) -> Result<()> {
+        if self.chars.next_if_eq(&'`').is_some() {
+            let mut body = String::new();
+            loop {
+                match self.chars.next() {
+                    Some('`') => break,
+                    Some('\\') => {
+                        if let Some(c) = self.chars.next() {
+                            body.push(c);
+                        }
+                    }
+                    Some(c) => body.push(c),
+                    None => return Err(anyhow!("Unterminated backtick substitution")),
+                }
+            }
+            word.text.push_str(&format!("`{}`", body));
+            self.substitutions.push(body);
+            return Ok(());
+        }
+
+        self.chars.next();
+        word.text.push('$');
+        match self.chars.peek() {
+            Some('(') => {
+                let body = self.balanced('(', ')')?;
+                word.text.push_str(&format!("({})", body));
+                if !(body.starts_with('(') && body.ends_with(')')) {
+                    self.substitutions.push(body);
+                }
+            }
+            Some('{') => {
+                let body = self.balanced('{', '}')?;
+                word.text.push_str(&format!("{{{}}}", body));
+            }
+            _ => {
+                while let Some(c) = self
+                    .chars
+                    .next_if(|&c| c.is_alphanumeric() || c == '_' || "@*#?$!-".contains(c))
+                {
+                    word.text.push(c);
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Reads up to the matching closing character, returning the text in between
+    fn balanced(&mut self, open: char, close: char) -> Result<String> {
+        self.chars.next();
+        let mut depth = 1;
+        let mut body = String::new();
+        let mut quote = None;
+        while let Some(c) = self.chars.next() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), '\\') => {
+                    body.push(c);
+                    if let Some(escaped) = self.chars.next() {
+                        body.push(escaped);
+                    }
+                    continue;
+                }
+                (None, '\'' | '"') => quote = Some(c),
+                (None, c) if c == open => depth += 1,
+                (None, c) if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(c);
+        }
+        Err(anyhow!("Unterminated {}{}", open, close))
+    }
+}
+
+fn redirect_kind(operator: &str) -> RedirectKind {
+    match operator {
+        "<" => RedirectKind::Input,
+        ">" | ">|" => RedirectKind::Output,
+        ">>" => RedirectKind::Append,
+        "&>" => RedirectKind::OutputAndError { append: false },
+        "&>>" => RedirectKind::OutputAndError { append: true },
+        "<&" | ">&" => RedirectKind::Duplicate,
+        "<<" | "<<-" => RedirectKind::HereDoc,
+        "<<<" => RedirectKind::HereString,
+        _ => RedirectKind::ReadWrite,
+    }
+}
+
+// Returns whether a word is a `NAME=value` assignment
+pub fn is_assignment(word: &Word) -> bool {
+    match word.text.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+impl Script {
+    // Parses a command line into its simple commands. Compound commands are flattened,
+    // so `if x; then y; fi` yields `x` and `y`.
+    pub fn parse(input: &str) -> Result<Script> {
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokens()?;
+
+        let mut commands = Vec::new();
+        let mut current = SimpleCommand::default();
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Word(word) => {
+                    if current.words.is_empty() && RESERVED_WORDS.contains(&word.text.as_str()) {
+                        continue;
+                    }
+                    if current.words.is_empty() && is_assignment(&word) {
+                        current.assignments.push(word);
+                    } else {
+                        current.words.push(word);
+                    }
+                }
+                Token::Redirect(fd, operator) => {
+                    let target = match tokens.next() {
+                        Some(Token::Word(word)) => word,
+                        _ => return Err(anyhow!("Missing target for redirection {}", operator)),
+                    };
+                    current.redirects.push(Redirect {
+                        fd,
+                        kind: redirect_kind(operator),
+                        target,
+                    });
+                }
+                Token::Operator(operator) => {
+                    let connector = match operator {
+                        "|" | "|&" => Some(Connector::Pipe),
+                        "&&" => Some(Connector::And),
+                        "||" => Some(Connector::Or),
+                        "&" => Some(Connector::Background),
+                        ";" | ";;" | "\n" => Some(Connector::Sequence),
+                        _ => None,
+                    };
+                    if current != SimpleCommand::default() {
+                        current.connector = connector;
+                        commands.push(std::mem::take(&mut current));
+                    }
+                }
+            }
+        }
+        if current != SimpleCommand::default() {
+            commands.push(current);
+        }
+
+        Ok(Script {
+            commands,
+            substitutions: lexer.substitutions,
+        })
+    }
+
+    // All simple commands, including those inside command substitutions
+    pub fn all_commands(&self) -> Vec<SimpleCommand> {
+        let mut commands = self.commands.clone();
+        for substitution in &self.substitutions {
+            if let Ok(script) = Script::parse(substitution) {
+                commands.extend(script.all_commands());
+            }
+        }
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(command: &SimpleCommand) -> Vec<&str> {
+        command
+            .words
+            .iter()
+            .map(|word| word.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn removes_quotes() {
+        let script = Script::parse(r#"echo 'a b' "c $HOME \" d" e\ f"#).unwrap();
+        let command = &script.commands[0];
+        assert_eq!(words(command), ["echo", "a b", "c $HOME \" d", "e f"]);
+        assert!(command.words[1].quoted && !command.words[1].expansion);
+        assert!(command.words[2].expansion);
+        assert!(!command.words[0].quoted);
+    }
+
+    #[test]
+    fn rejects_unterminated_quotes() {
+        assert!(Script::parse("echo 'a").is_err());
+        assert!(Script::parse("echo \"a").is_err());
+        assert!(Script::parse("echo $(ls").is_err());
+        assert!(Script::parse("echo `ls").is_err());
+    }
+
+    #[test]
+    fn marks_globs() {
+        let script = Script::parse("rm *.o '*.c'").unwrap();
+        assert!(script.commands[0].words[1].glob);
+        assert!(!script.commands[0].words[2].glob);
+    }
+
+    #[test]
+    fn splits_commands_on_connectors() {
+        let script = Script::parse("a | b && c || d; e & f\ng").unwrap();
+        let connectors = script
+            .commands
+            .iter()
+            .map(|command| command.connector)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            connectors,
+            [
+                Some(Connector::Pipe),
+                Some(Connector::And),
+                Some(Connector::Or),
+                Some(Connector::Sequence),
+                Some(Connector::Background),
+                Some(Connector::Sequence),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn flattens_compound_commands() {
+        let script = Script::parse("if test -f x; then rm x; fi").unwrap();
+        let programs = script
+            .commands
+            .iter()
+            .filter_map(SimpleCommand::program)
+            .collect::<Vec<_>>();
+        assert_eq!(programs, ["test", "rm"]);
+    }
+
+    #[test]
+    fn separates_assignments_and_comments() {
+        let script = Script::parse("FOO=1 BAR=2 /usr/bin/env ls # rm -rf /").unwrap();
+        let command = &script.commands[0];
+        assert_eq!(command.assignments.len(), 2);
+        assert_eq!(command.program(), Some("env"));
+        assert_eq!(words(command), ["/usr/bin/env", "ls"]);
+    }
+
+    #[test]
+    fn parses_redirects_with_file_descriptors() {
+        let script = Script::parse("cmd > out 2>> err 2>&1 &> all < in 3<> rw").unwrap();
+        let redirects = script.commands[0]
+            .redirects
+            .iter()
+            .map(|redirect| (redirect.fd, redirect.kind, redirect.target.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            redirects,
+            [
+                (None, RedirectKind::Output, "out"),
+                (Some(2), RedirectKind::Append, "err"),
+                (Some(2), RedirectKind::Duplicate, "1"),
+                (None, RedirectKind::OutputAndError { append: false }, "all"),
+                (None, RedirectKind::Input, "in"),
+                (Some(3), RedirectKind::ReadWrite, "rw"),
+            ]
+        );
+        assert_eq!(words(&script.commands[0]), ["cmd"]);
+    }
+
+    #[test]
+    fn quoted_digits_are_not_file_descriptors() {
+        let script = Script::parse("echo '2'> out").unwrap();
+        assert_eq!(words(&script.commands[0]), ["echo", "2"]);
+        assert_eq!(script.commands[0].redirects[0].fd, None);
+    }
+
+    #[test]
+    fn skips_heredoc_bodies() {
+        let script = Script::parse("cat <<EOF > out\nrm -rf /\nEOF\nls").unwrap();
+        let programs = script
+            .commands
+            .iter()
+            .filter_map(SimpleCommand::program)
+            .collect::<Vec<_>>();
+        assert_eq!(programs, ["cat", "ls"]);
+        assert_eq!(script.commands[0].redirects[0].kind, RedirectKind::HereDoc);
+
+        let script = Script::parse("cat <<-END\n\trm x\n\tEND\necho done").unwrap();
+        assert_eq!(script.commands.len(), 2);
+        assert_eq!(script.commands[1].program(), Some("echo"));
+    }
+
+    #[test]
+    fn collects_substitutions() {
+        let script = Script::parse("echo $(rm -rf x) `whoami` $((1 + 2)) ${HOME}").unwrap();
+        assert_eq!(script.substitutions, ["rm -rf x", "whoami"]);
+        let programs = script
+            .all_commands()
+            .iter()
+            .filter_map(|command| command.program().map(String::from))
+            .collect::<Vec<_>>();
+        assert_eq!(programs, ["echo", "rm", "whoami"]);
+    }
+
+    #[test]
+    fn collects_nested_substitutions() {
+        let script = Script::parse("echo \"$(cat $(ls))\"").unwrap();
+        assert_eq!(script.substitutions, ["cat $(ls)"]);
+        assert_eq!(script.all_commands().len(), 3);
+    }
+}
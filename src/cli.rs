@@ -0,0 +1,342 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow};
+
+use crate::shell::ShellKind;
+
+pub const HELP: &str = "\
+AIA Terminal Assistant
+
+Usage: aia [OPTIONS] [PROMPT]...
+       aia [OPTIONS] <COMMAND>
+
+Commands:
+  ask <PROMPT>...          Start a conversation with a prompt (default)
+  run <GOAL>...            Work towards a goal in agent mode
+  config [show|path|edit]  Show, locate or edit the configuration file
+  history [COUNT]          Show the most recently executed commands
+  undo                     Restore the files changed by the last executed command
+  shell-init <SHELL>       Print the Ctrl-G keybinding for bash, zsh or fish
+  help                     Print this help
+
+Options:
+  -m, --model <MODEL>      Use a different model than the configured one
+  -c, --config <PATH>      Read the configuration from PATH
+  -p, --profile <NAME>     Apply the [profiles.NAME] section of the configuration
+  -s, --shell <SHELL>      Write and run commands for bash, zsh, fish, sh or nu
+  -n, --non-interactive    Answer a single prompt without asking anything
+  -y, --yes                Execute suggested commands without asking, unless high risk
+      --dry-run            Explain suggested commands instead of executing them
+  -o, --output <FORMAT>    Print turns as text (default) or json, one object per line
+  -v, --verbose            Show the context and raw replies sent to and from the model
+  -q, --quiet              Hide thoughts and token usage
+  -h, --help               Print help
+  -V, --version            Print version
+
+Exit codes with --non-interactive:
+  0  A command was suggested, or executed successfully with --yes
+  1  An error occurred
+  2  The arguments are invalid
+  3  The model answered
+  4  The model asked a question
+  5  The command was not executed because of its risk
+  6  The command executed with --yes failed
+";
+
+// Exit codes of non-interactive mode, see HELP
+pub const EXIT_COMMAND: i32 = 0;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_ANSWER: i32 = 3;
+pub const EXIT_QUESTION: i32 = 4;
+pub const EXIT_NOT_EXECUTED: i32 = 5;
+pub const EXIT_COMMAND_FAILED: i32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    // One JSON object per turn on stdout
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigAction {
+    Show,
+    Path,
+    Edit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliCommand {
+    // Start a conversation, optionally with a first prompt
+    Ask(Option<String>),
+    // Start a conversation in agent mode
+    Run(Option<String>),
+    Config(ConfigAction),
+    History(usize),
+    // Restore the most recent snapshot
+    Undo,
+    // Print the keybinding script for a shell
+    ShellInit(String),
+    Help,
+    Version,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cli {
+    pub model: Option<String>,
+    pub config: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub shell: Option<ShellKind>,
+    pub non_interactive: bool,
+    pub yes: bool,
+    pub dry_run: bool,
+    pub verbosity: Verbosity,
+    pub output: OutputFormat,
+    pub command: CliCommand,
+}
+
+fn join_prompt(words: &[String]) -> Option<String> {
+    Some(words.join(" ")).filter(|prompt| !prompt.trim().is_empty())
+}
+
+impl Cli {
+    // Parses the arguments following the program name. Options are only recognized before
+    // the first positional word; everything from there on is the subcommand or the prompt,
+    // so `aia what does ls -h do` keeps working.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli> {
+        let mut cli = Cli {
+            model: None,
+            config: None,
+            profile: None,
+            shell: None,
+            non_interactive: false,
+            yes: false,
+            dry_run: false,
+            verbosity: Verbosity::Normal,
+            output: OutputFormat::Text,
+            command: CliCommand::Ask(None),
+        };
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if !positional.is_empty() {
+                positional.push(arg);
+                continue;
+            }
+            // `--option=value` is the same as `--option value`
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |option: &str| -> Result<String> {
+                match inline_value {
+                    Some(value) => Ok(value.to_string()),
+                    None => args
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing value for {}", option)),
+                }
+            };
+
+            match name.as_str() {
+                "--" => {
+                    positional.extend(args.by_ref());
+                }
+                "-m" | "--model" => cli.model = Some(value("--model")?),
+                "-c" | "--config" => cli.config = Some(PathBuf::from(value("--config")?)),
+                "-p" | "--profile" => cli.profile = Some(value("--profile")?),
+                "-s" | "--shell" => {
+                    let name = value("--shell")?;
+                    cli.shell = Some(ShellKind::parse(&name).ok_or_else(|| {
+                        anyhow!("Unknown shell {}, expected bash, zsh, fish, sh or nu", name)
+                    })?);
+                }
+                "-n" | "--non-interactive" => cli.non_interactive = true,
+                "-y" | "--yes" => cli.yes = true,
+                "--dry-run" => cli.dry_run = true,
+                "-o" | "--output" => {
+                    cli.output = match value("--output")?.as_str() {
+                        "text" => OutputFormat::Text,
+                        "json" => OutputFormat::Json,
+                        other => {
+                            return Err(anyhow!(
+                                "Unknown output format {}, expected text or json",
+                                other
+                            ));
+                        }
+                    }
+                }
+                "-v" | "--verbose" => cli.verbosity = Verbosity::Verbose,
+                "-q" | "--quiet" => cli.verbosity = Verbosity::Quiet,
+                "-h" | "--help" => {
+                    return Ok(Cli {
+                        command: CliCommand::Help,
+                        ..cli
+                    });
+                }
+                "-V" | "--version" => {
+                    return Ok(Cli {
+                        command: CliCommand::Version,
+                        ..cli
+                    });
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(anyhow!(
+                        "Unknown option {}, use `--` before a prompt that starts with a dash",
+                        arg
+                    ));
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        cli.command = match positional.first().map(String::as_str) {
+            None => CliCommand::Ask(None),
+            Some("ask") => CliCommand::Ask(join_prompt(&positional[1..])),
+            Some("run") => CliCommand::Run(join_prompt(&positional[1..])),
+            Some("help") if positional.len() == 1 => CliCommand::Help,
+            Some("config") if positional.len() == 1 => CliCommand::Config(ConfigAction::Show),
+            Some("config") if positional.len() == 2 => match positional[1].as_str() {
+                "show" => CliCommand::Config(ConfigAction::Show),
+                "path" => CliCommand::Config(ConfigAction::Path),
+                "edit" => CliCommand::Config(ConfigAction::Edit),
+                _ => CliCommand::Ask(join_prompt(&positional)),
+            },
+            Some("history") if positional.len() == 1 => CliCommand::History(20),
+            Some("history") if positional.len() == 2 => match positional[1].parse() {
+                Ok(count) => CliCommand::History(count),
+                Err(_) => CliCommand::Ask(join_prompt(&positional)),
+            },
+            Some("undo") if positional.len() == 1 => CliCommand::Undo,
+            Some("shell-init") if positional.len() == 2 => {
+                CliCommand::ShellInit(positional[1].clone())
+            }
+            Some(_) => CliCommand::Ask(join_prompt(&positional)),
+        };
+        Ok(cli)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn prompt(args: &[&str]) -> Option<String> {
+        match parse(args).unwrap().command {
+            CliCommand::Ask(prompt) => prompt,
+            command => panic!("expected a prompt, got {:?}", command),
+        }
+    }
+
+    #[test]
+    fn parses_options_before_the_prompt() {
+        let cli = parse(&[
+            "-m",
+            "gpt-4o",
+            "--config=aia.toml",
+            "-p",
+            "work",
+            "--shell",
+            "fish",
+            "-n",
+            "-y",
+            "--dry-run",
+            "-o",
+            "json",
+            "-q",
+            "list",
+            "files",
+        ])
+        .unwrap();
+        assert_eq!(cli.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(cli.config, Some(PathBuf::from("aia.toml")));
+        assert_eq!(cli.profile.as_deref(), Some("work"));
+        assert_eq!(cli.shell, Some(ShellKind::Fish));
+        assert!(cli.non_interactive && cli.yes && cli.dry_run);
+        assert_eq!(cli.output, OutputFormat::Json);
+        assert_eq!(cli.verbosity, Verbosity::Quiet);
+        assert_eq!(cli.command, CliCommand::Ask(Some("list files".to_string())));
+    }
+
+    #[test]
+    fn options_after_the_first_word_belong_to_the_prompt() {
+        assert_eq!(
+            prompt(&["what", "does", "ls", "-h", "do"]).unwrap(),
+            "what does ls -h do"
+        );
+        assert_eq!(
+            prompt(&["show", "lines", "without", "foo", "using", "grep", "-v"]).unwrap(),
+            "show lines without foo using grep -v"
+        );
+        assert_eq!(
+            prompt(&["explain", "tar", "-c", "-p"]).unwrap(),
+            "explain tar -c -p"
+        );
+        assert_eq!(
+            prompt(&["how", "do", "I", "use", "curl", "-o", "file"]).unwrap(),
+            "how do I use curl -o file"
+        );
+
+        let cli = parse(&["-v", "list", "files", "-q"]).unwrap();
+        assert_eq!(cli.verbosity, Verbosity::Verbose);
+        assert_eq!(
+            cli.command,
+            CliCommand::Ask(Some("list files -q".to_string()))
+        );
+    }
+
+    #[test]
+    fn double_dash_starts_the_prompt() {
+        assert_eq!(prompt(&["-n", "--", "-h", "flag"]).unwrap(), "-h flag");
+        assert!(parse(&["--unknown", "prompt"]).is_err());
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        assert_eq!(parse(&[]).unwrap().command, CliCommand::Ask(None));
+        assert_eq!(parse(&["-h"]).unwrap().command, CliCommand::Help);
+        assert_eq!(parse(&["--version"]).unwrap().command, CliCommand::Version);
+        assert_eq!(
+            parse(&["run", "clean", "up", "-f"]).unwrap().command,
+            CliCommand::Run(Some("clean up -f".to_string()))
+        );
+        assert_eq!(
+            parse(&["config", "path"]).unwrap().command,
+            CliCommand::Config(ConfigAction::Path)
+        );
+        assert_eq!(
+            parse(&["history", "5"]).unwrap().command,
+            CliCommand::History(5)
+        );
+        assert_eq!(parse(&["undo"]).unwrap().command, CliCommand::Undo);
+        assert_eq!(
+            parse(&["shell-init", "zsh"]).unwrap().command,
+            CliCommand::ShellInit("zsh".to_string())
+        );
+        // Subcommand names followed by free text are prompts
+        assert_eq!(
+            prompt(&["history", "of", "this", "repo"]).unwrap(),
+            "history of this repo"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_option_values() {
+        assert!(parse(&["-o", "yaml"]).is_err());
+        assert!(parse(&["--shell", "cmd"]).is_err());
+        assert!(parse(&["--model"]).is_err());
+    }
+}
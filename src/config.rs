@@ -1,17 +1,183 @@
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::io::Read;
 use std::path::Path;
 
+use crate::shell::ShellKind;
+
+// The LLM provider used to generate responses
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    OpenAI,
+    Ollama,
+    Anthropic,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
+    #[serde(default)]
+    pub provider: Provider,
+    #[serde(default)]
     pub openai_token: String,
+    #[serde(default)]
     pub openai_model: String,
+    // Base URL of an OpenAI-compatible API, e.g. a proxy or a local LLM server
+    #[serde(default)]
+    pub openai_api_base: Option<String>,
+    #[serde(default)]
+    pub openai_organization: Option<String>,
+    #[serde(default)]
+    pub openai_project: Option<String>,
+    // Extra HTTP headers sent with every request
+    #[serde(default)]
+    pub openai_headers: HashMap<String, String>,
+    // Azure OpenAI deployment name; openai_api_base must point to the Azure resource
+    #[serde(default)]
+    pub azure_deployment: Option<String>,
+    #[serde(default = "default_azure_api_version")]
+    pub azure_api_version: String,
+    // Maximum number of requests per response, covering both failed and invalid replies
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    // Delay before retrying a failed request, doubled after every retry
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+    // Maximum number of bytes of command output sent back to the model
+    #[serde(default = "default_output_limit")]
+    pub output_limit: usize,
+    // Number of directory levels listed in the context
+    #[serde(default = "default_context_depth")]
+    pub context_depth: usize,
+    // Maximum number of characters of the directory listing in the context, about four per token
+    #[serde(default = "default_context_budget")]
+    pub context_budget: usize,
+    // Seconds after which an executed command is killed, 0 to let commands run indefinitely
+    #[serde(default)]
+    pub command_timeout: u64,
+    // Run commands in a pseudo-terminal so interactive programs work; disable to use plain pipes
+    #[serde(default = "default_pty")]
+    pub pty: bool,
+    // Save the files a command is about to change so that `aia undo` can restore them
+    #[serde(default = "default_undo")]
+    pub undo: bool,
+    // Let the model chain commands towards a goal, feeding each output back automatically
+    #[serde(default)]
+    pub agent_mode: bool,
+    // Maximum number of commands executed in agent mode per user input
+    #[serde(default = "default_agent_max_steps")]
+    pub agent_max_steps: u32,
+    // Command prefixes that agent mode executes without asking, e.g. "ls" or "git status"
+    #[serde(default = "default_auto_approve")]
+    pub auto_approve: Vec<String>,
+    // Commands matching these patterns (`*` and `?` wildcards) are considered low risk
+    #[serde(default)]
+    pub risk_allow: Vec<String>,
+    // Commands matching these patterns are never executed
+    #[serde(default)]
+    pub risk_deny: Vec<String>,
+    // Shell that runs commands and that the model writes them for; defaults to $SHELL
+    #[serde(default)]
+    pub shell: Option<ShellKind>,
+    // Constrain replies with a JSON schema; disable for servers that do not support it
+    #[serde(default = "default_structured_output")]
+    pub structured_output: bool,
+    #[serde(default = "default_ollama_url")]
+    pub ollama_url: String,
+    // Installed Ollama model to use; when empty, aia asks which one to use
+    #[serde(default)]
+    pub ollama_model: String,
+    #[serde(default)]
+    pub anthropic_token: String,
+    #[serde(default = "default_anthropic_model")]
+    pub anthropic_model: String,
+    // Base URL of the Anthropic API, e.g. a proxy or a local mock server
+    #[serde(default = "default_anthropic_api_base")]
+    pub anthropic_api_base: String,
+    #[serde(default = "default_anthropic_max_tokens")]
+    pub anthropic_max_tokens: u32,
+}
+
+fn default_azure_api_version() -> String {
+    "2024-10-21".to_string()
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_retry_delay_ms() -> u64 {
+    1000
+}
+
+fn default_output_limit() -> usize {
+    8000
+}
+
+fn default_context_depth() -> usize {
+    3
+}
+
+fn default_context_budget() -> usize {
+    4000
+}
+
+fn default_pty() -> bool {
+    true
+}
+
+fn default_undo() -> bool {
+    true
+}
+
+fn default_agent_max_steps() -> u32 {
+    10
+}
+
+fn default_auto_approve() -> Vec<String> {
+    [
+        "ls",
+        "pwd",
+        "cat",
+        "head",
+        "tail",
+        "wc",
+        "grep",
+        "which",
+        "git status",
+        "git log",
+        "git diff",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_structured_output() -> bool {
+    true
+}
+
+fn default_ollama_url() -> String {
+    "http://localhost:11434".to_string()
+}
+
+fn default_anthropic_model() -> String {
+    "claude-3-5-haiku-latest".to_string()
+}
+
+fn default_anthropic_api_base() -> String {
+    "https://api.anthropic.com".to_string()
+}
+
+fn default_anthropic_max_tokens() -> u32 {
+    4096
 }
 
 impl Config {
-    pub fn read(config_path: &Path) -> anyhow::Result<Config> {
+    // Writes the default config file unless one exists
+    pub fn create_default(config_path: &Path) -> anyhow::Result<()> {
         let config_dir = config_path
             .parent()
             .context("Failed to get configuration directory")?;
@@ -21,6 +187,11 @@ impl Config {
             fs::write(config_path, include_str!("../config_template.conf"))
                 .context("Failed to write default config file")?;
         }
+        Ok(())
+    }
+
+    pub fn read(config_path: &Path, profile: Option<&str>) -> anyhow::Result<Config> {
+        Config::create_default(config_path)?;
 
         let mut file = std::fs::File::open(config_path).context("Failed to open config file")?;
 
@@ -28,8 +199,33 @@ impl Config {
         file.read_to_string(&mut contents)
             .context("Failed to read config file")?;
 
-        let config: Config = toml::from_str(&contents).context("Failed to parse config file")?;
+        let mut table: toml::Table =
+            toml::from_str(&contents).context("Failed to parse config file")?;
+
+        // Profiles override the top-level values, e.g. [profiles.work]
+        let profiles = table.remove("profiles");
+        if let Some(name) = profile {
+            let overrides = profiles
+                .as_ref()
+                .and_then(|profiles| profiles.get(name))
+                .and_then(|profile| profile.as_table())
+                .with_context(|| format!("Profile {} not found in config file", name))?;
+            for (key, value) in overrides {
+                table.insert(key.clone(), value.clone());
+            }
+        }
+
+        let config: Config = table.try_into().context("Failed to parse config file")?;
 
         Ok(config)
     }
+
+    // Overrides the model of the configured provider
+    pub fn set_model(&mut self, model: String) {
+        match self.provider {
+            Provider::OpenAI => self.openai_model = model,
+            Provider::Ollama => self.ollama_model = model,
+            Provider::Anthropic => self.anthropic_model = model,
+        }
+    }
 }
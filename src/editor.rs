@@ -0,0 +1,87 @@
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::fsutil;
+
+// Exit status of the line editor script when bash is too old to pre-fill the prompt
+const NO_PREFILL: i32 = 3;
+
+// Edits a single line in a readline prompt pre-filled with the current text. Returns None
+// when bash lacks `read -i`, which needs bash 4 or later and is missing from macOS' bash 3.2.
+fn edit_inline(command: &str) -> Result<Option<String>> {
+    let output = Command::new("bash")
+        .arg("-c")
+        .arg(format!(
+            r#"((BASH_VERSINFO[0] >= 4)) || exit {}; read -r -e -i "$1" -p "> " line && printf '%s' "$line""#,
+            NO_PREFILL
+        ))
+        .arg("aia")
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .output()
+        .context("Failed to start line editor")?;
+
+    if output.status.code() == Some(NO_PREFILL) {
+        return Ok(None);
+    }
+    if !output.status.success() {
+        return Err(anyhow!("Editing was cancelled"));
+    }
+    String::from_utf8(output.stdout)
+        .map(Some)
+        .context("Edited command is not valid UTF-8")
+}
+
+// Opens a file in $VISUAL or $EDITOR, falling back to vi
+pub fn edit_file(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+
+    // Run through sh so editors configured with arguments, like `code --wait`, work
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("aia")
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to start editor {}", editor))?;
+
+    if !status.success() {
+        return Err(anyhow!("Editor exited with {}", status));
+    }
+    Ok(())
+}
+
+// Edits the text in a temporary file
+fn edit_in_editor(command: &str) -> Result<String> {
+    let dir = fsutil::create_private_dir(&std::env::temp_dir(), "aia-command")?;
+    let path = dir.join("command.sh");
+    let result = fs::write(&path, format!("{}\n", command))
+        .context("Failed to write command file")
+        .and_then(|_| edit_file(&path));
+    let edited = fs::read_to_string(&path).context("Failed to read edited command");
+    let _ = fs::remove_dir_all(&dir);
+    result?;
+    Ok(edited?.trim_end().to_string())
+}
+
+// Lets the user change a command: single lines are edited in place, scripts in an editor,
+// as are single lines when the shell cannot edit them in place
+pub fn edit_command(command: &str) -> Result<String> {
+    if !command.contains('\n')
+        && let Some(edited) = edit_inline(command)?
+    {
+        return Ok(edited);
+    }
+    edit_in_editor(command)
+}
@@ -0,0 +1,535 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    ffi::OsString,
+    fs,
+    io::{Read, Write},
+    os::unix::{ffi::OsStringExt, process::CommandExt, process::ExitStatusExt},
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::fsutil;
+use crate::pty::{self, Pty, PtyReader, RawMode};
+#[cfg(target_os = "linux")]
+use crate::sandbox::Sandbox;
+use crate::shell::{Shell, ShellKind};
+
+// Commands can only be sandboxed on Linux
+#[cfg(not(target_os = "linux"))]
+pub enum Sandbox {}
+
+// Keeps the beginning and the end of a command's output within a size limit
+struct OutputBuffer {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    half_limit: usize,
+    omitted: usize,
+}
+
+impl OutputBuffer {
+    fn new(limit: usize) -> Self {
+        Self {
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            half_limit: limit / 2,
+            omitted: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let head_space = self.half_limit - self.head.len();
+        let (head, rest) = bytes.split_at(head_space.min(bytes.len()));
+        self.head.extend_from_slice(head);
+
+        self.tail.extend(rest);
+        let overflow = self.tail.len().saturating_sub(self.half_limit);
+        self.tail.drain(..overflow);
+        self.omitted += overflow;
+    }
+
+    fn into_string(mut self) -> String {
+        let head = String::from_utf8_lossy(&self.head);
+        let tail = String::from_utf8_lossy(self.tail.make_contiguous());
+        if self.omitted == 0 {
+            format!("{}{}", head, tail)
+        } else {
+            format!(
+                "{}\n[... {} bytes omitted ...]\n{}",
+                head, self.omitted, tail
+            )
+        }
+    }
+}
+
+// How a command was stopped before it finished on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    // The user pressed Ctrl-C
+    Interrupted,
+    TimedOut(Duration),
+}
+
+impl Termination {
+    pub fn label(self) -> &'static str {
+        match self {
+            Termination::Interrupted => "interrupted",
+            Termination::TimedOut(_) => "timed_out",
+        }
+    }
+}
+
+// How commands are executed
+pub struct ExecOptions {
+    // Maximum number of bytes of output kept for the model
+    pub output_limit: usize,
+    // Commands still running after this are killed
+    pub timeout: Option<Duration>,
+    // Show the command's stdout on stderr to keep stdout free for JSON
+    pub stdout_to_stderr: bool,
+    // Run commands in a pseudo-terminal when aia runs in a terminal
+    pub pty: bool,
+}
+
+// The result of an executed command
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    pub termination: Option<Termination>,
+    // Combined stdout and stderr, truncated in the middle if too long
+    pub output: String,
+    // Whether the command changed the working directory
+    pub cwd_changed: bool,
+}
+
+impl CommandOutput {
+    // Describes the execution for the model
+    pub fn to_message(&self, command: &str) -> String {
+        let status = match (self.termination, self.status.code()) {
+            (Some(Termination::Interrupted), _) => "interrupted by the user (Ctrl-C)".to_string(),
+            (Some(Termination::TimedOut(timeout)), _) => format!(
+                "timed out after {} seconds and was killed",
+                timeout.as_secs()
+            ),
+            (None, Some(code)) => format!("exit code {}", code),
+            (None, None) => "terminated by a signal".to_string(),
+        };
+        let output = if self.output.trim().is_empty() {
+            "(no output)".to_string()
+        } else {
+            format!("```\n{}\n```", self.output.trim_end())
+        };
+        format!(
+            "User executed command: {}\nStatus: {}\nOutput:\n{}",
+            command, status, output
+        )
+    }
+}
+
+// Variables that the shell sets itself and that must not be carried over
+const SHELL_VARIABLES: &[&str] = &["_", "SHLVL", "OLDPWD"];
+
+// Quotes a path for the shells that support state tracking
+fn quote(path: &std::path::Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+// The working directory and environment carried over from one executed command to the next,
+// so consecutive commands behave like in a terminal even though each runs in a new shell.
+// When a command exits, its shell writes its cwd and environment to files in `state_dir`.
+pub struct ShellSession {
+    env: HashMap<OsString, OsString>,
+    state_dir: PathBuf,
+}
+
+impl ShellSession {
+    pub fn new() -> Result<Self> {
+        let state_dir = fsutil::create_private_dir(&std::env::temp_dir(), "aia-session")
+            .context("Failed to create session directory")?;
+        Ok(Self {
+            env: std::env::vars_os().collect(),
+            state_dir,
+        })
+    }
+
+    // Adds an exit hook that saves the shell's state; nushell's state is not tracked
+    fn wrap(&self, command: &str, shell: &Shell) -> String {
+        let cwd_file = quote(&self.state_dir.join("cwd"));
+        let env_file = quote(&self.state_dir.join("env"));
+        match shell.kind {
+            ShellKind::Bash | ShellKind::Zsh | ShellKind::Sh => format!(
+                "trap 'pwd > {}; env -0 > {}' EXIT\n{}",
+                cwd_file, env_file, command
+            ),
+            ShellKind::Fish => format!(
+                "function __aia_save_state --on-event fish_exit; pwd > {}; env -0 > {}; end\n{}",
+                cwd_file, env_file, command
+            ),
+            ShellKind::Nu => command.to_string(),
+        }
+    }
+
+    // Applies the state saved by the last command. Returns whether the cwd changed.
+    fn restore(&mut self) -> Result<bool> {
+        let cwd_file = self.state_dir.join("cwd");
+        let env_file = self.state_dir.join("env");
+        let cwd = fs::read_to_string(&cwd_file).ok();
+        let env = fs::read(&env_file).ok();
+        let _ = fs::remove_file(&cwd_file);
+        let _ = fs::remove_file(&env_file);
+
+        // An incomplete state means the shell was killed or lacks `env -0`
+        if let Some(env) = env.filter(|env| !env.is_empty()) {
+            self.env = env
+                .split(|&byte| byte == 0)
+                .filter_map(|entry| {
+                    let separator = entry.iter().position(|&byte| byte == b'=')?;
+                    let (name, value) = entry.split_at(separator);
+                    Some((
+                        OsString::from_vec(name.to_vec()),
+                        OsString::from_vec(value[1..].to_vec()),
+                    ))
+                })
+                .filter(|(name, _)| !SHELL_VARIABLES.iter().any(|variable| name == variable))
+                .collect();
+        }
+
+        let Some(cwd) = cwd.map(|cwd| PathBuf::from(cwd.trim_end_matches('\n'))) else {
+            return Ok(false);
+        };
+        let current = std::env::current_dir().context("Failed to get current working directory")?;
+        // The directory may have been removed by the command itself
+        if cwd.as_os_str().is_empty() || cwd == current || std::env::set_current_dir(&cwd).is_err()
+        {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.state_dir);
+    }
+}
+
+// Copies a child's output stream to the terminal while recording it
+fn tee<R: Read, W: Write>(
+    mut source: R,
+    mut sink: W,
+    buffer: Arc<Mutex<OutputBuffer>>,
+) -> Result<()> {
+    let mut chunk = [0; 8192];
+    loop {
+        let read = source
+            .read(&mut chunk)
+            .context("Failed to read command output")?;
+        if read == 0 {
+            return Ok(());
+        }
+        sink.write_all(&chunk[..read])?;
+        sink.flush()?;
+        buffer
+            .lock()
+            .map_err(|_| anyhow!("Output buffer lock poisoned"))?
+            .push(&chunk[..read]);
+    }
+}
+
+// Set by the SIGINT handler while a command runs
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Time a command gets to exit after SIGTERM before it is killed
+const KILL_GRACE: Duration = Duration::from_secs(2);
+
+// Sends a signal to every process of a command
+fn signal_group(group: libc::pid_t, signal: libc::c_int) {
+    unsafe {
+        libc::kill(-group, signal);
+    }
+}
+
+// Makes a process group the terminal's foreground group, so it receives Ctrl-C and may read input.
+// SIGTTOU is ignored because the caller may itself be in a background group.
+fn set_foreground(group: libc::pid_t) {
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, group);
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+// Executes a command in the user's shell, showing its output and capturing the end of it.
+// On a terminal the command gets a pseudo-terminal of its own, so interactive programs like
+// editors, pagers and password prompts work. Otherwise its output is piped, and it runs in its
+// own process group made the terminal's foreground group. Either way Ctrl-C reaches only the
+// command, and a timeout stops all of its processes.
+pub fn execute_command(
+    command: &str,
+    shell: &Shell,
+    session: &mut ShellSession,
+    options: &ExecOptions,
+    sandbox: Option<&Sandbox>,
+) -> Result<CommandOutput> {
+    let terminal = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+    let pty = if options.pty && terminal && !options.stdout_to_stderr {
+        Some(Pty::open(libc::STDIN_FILENO)?)
+    } else {
+        None
+    };
+
+    // Sandboxed commands cannot change the session, so their state is not tracked
+    let script = match sandbox {
+        Some(_) => command.to_string(),
+        None => session.wrap(command, shell),
+    };
+    let mut process = Command::new(&shell.program);
+    process.arg("-c").arg(script).env_clear().envs(&session.env);
+    #[cfg(target_os = "linux")]
+    if let Some(sandbox) = sandbox {
+        unsafe {
+            process.pre_exec(sandbox.setup()?);
+        }
+    }
+    // Only async-signal-safe calls are made between fork and exec
+    match &pty {
+        Some(pty) => {
+            process
+                .stdin(Stdio::from(pty.slave.try_clone()?))
+                .stdout(Stdio::from(pty.slave.try_clone()?))
+                .stderr(Stdio::from(pty.slave.try_clone()?));
+            unsafe {
+                process.pre_exec(|| {
+                    libc::setsid();
+                    libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY as _, 0);
+                    Ok(())
+                });
+            }
+        }
+        None => {
+            process.stdout(Stdio::piped()).stderr(Stdio::piped());
+            unsafe {
+                process.pre_exec(move || {
+                    libc::setpgid(0, 0);
+                    if terminal {
+                        set_foreground(libc::getpid());
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+    let mut child = match (process.spawn(), sandbox) {
+        (Ok(child), _) => child,
+        (Err(err), Some(_)) => {
+            return Err(err)
+                .context("Failed to start the sandbox, user namespaces may be disabled");
+        }
+        (Err(err), None) => return Err(err).context("Failed to execute command"),
+    };
+    let group = child.id() as libc::pid_t;
+    // The pseudo-terminal reports end of file only once no copy of the slave side is left open
+    drop(process);
+    let master = pty.map(|Pty { master, .. }| master);
+
+    // Ctrl-C while aia keeps the terminal, or a SIGINT sent to aia, is forwarded to the command
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    let previous_handler = unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        )
+    };
+
+    let buffer = Arc::new(Mutex::new(OutputBuffer::new(options.output_limit)));
+    let mut output_threads = Vec::new();
+    let mut raw_mode = None;
+    let mut input_thread = None;
+    let stop_input = Arc::new(AtomicBool::new(false));
+    match &master {
+        Some(master) => {
+            let reader = PtyReader::new(master)?;
+            let buffer = buffer.clone();
+            output_threads.push(thread::spawn(move || {
+                tee(reader, std::io::stdout(), buffer)
+            }));
+            raw_mode = Some(RawMode::enable(libc::STDIN_FILENO)?);
+            input_thread = Some(pty::forward_input(master, stop_input.clone())?);
+        }
+        None => {
+            let stdout = child.stdout.take().context("Failed to capture stdout")?;
+            let stderr = child.stderr.take().context("Failed to capture stderr")?;
+            let stdout_to_stderr = options.stdout_to_stderr;
+            let buffer_out = buffer.clone();
+            output_threads.push(thread::spawn(move || {
+                if stdout_to_stderr {
+                    tee(stdout, std::io::stderr(), buffer_out)
+                } else {
+                    tee(stdout, std::io::stdout(), buffer_out)
+                }
+            }));
+            let buffer_err = buffer.clone();
+            output_threads.push(thread::spawn(move || {
+                tee(stderr, std::io::stderr(), buffer_err)
+            }));
+        }
+    }
+
+    // Background processes started by the command may keep its output open after the shell
+    // exits, so Ctrl-C and the timeout keep working until the output is drained too
+    let started = Instant::now();
+    let mut termination = None;
+    let mut terminated_at = None;
+    let mut pty_size = None;
+    let mut status = None;
+    loop {
+        if status.is_none() {
+            match child.try_wait() {
+                Ok(Some(exit_status)) => status = Some(Ok(exit_status)),
+                Ok(None) => {}
+                Err(err) => status = Some(Err(err)),
+            }
+        }
+        let drained = output_threads.iter().all(|handle| handle.is_finished());
+        match &status {
+            Some(Err(_)) => break,
+            Some(Ok(_)) if drained => break,
+            // Processes that left the command's group cannot be killed; stop waiting for them
+            Some(Ok(_))
+                if terminated_at.is_some_and(|terminated_at: Instant| {
+                    terminated_at.elapsed() >= KILL_GRACE * 2
+                }) =>
+            {
+                break;
+            }
+            _ => {}
+        }
+
+        if INTERRUPTED.swap(false, Ordering::SeqCst) {
+            termination.get_or_insert(Termination::Interrupted);
+            // Background processes ignore SIGINT, so once the shell is gone they are terminated
+            if status.is_some() {
+                signal_group(group, libc::SIGTERM);
+                terminated_at.get_or_insert_with(Instant::now);
+            } else {
+                signal_group(group, libc::SIGINT);
+            }
+        }
+        if let Some(timeout) = options.timeout
+            && terminated_at.is_none()
+            && started.elapsed() >= timeout
+        {
+            signal_group(group, libc::SIGTERM);
+            termination = Some(Termination::TimedOut(timeout));
+            terminated_at = Some(Instant::now());
+        }
+        if let Some(terminated_at) = terminated_at
+            && terminated_at.elapsed() >= KILL_GRACE
+        {
+            signal_group(group, libc::SIGKILL);
+        }
+        // Follow resizes of the real terminal
+        if let Some(master) = &master {
+            pty::sync_size(master, libc::STDIN_FILENO, &mut pty_size);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    stop_input.store(true, Ordering::SeqCst);
+    if let Some(input_thread) = input_thread {
+        let _ = input_thread.join();
+    }
+    drop(raw_mode);
+    if terminal && master.is_none() {
+        set_foreground(unsafe { libc::getpgrp() });
+    }
+    unsafe {
+        libc::signal(libc::SIGINT, previous_handler);
+    }
+    let status = status
+        .context("Command status missing")?
+        .context("Failed to wait for command")?;
+
+    for handle in output_threads {
+        if handle.is_finished() {
+            handle
+                .join()
+                .map_err(|_| anyhow!("Output thread panicked"))??;
+        }
+    }
+
+    // Output threads still reading for escaped processes keep their copy of the buffer
+    let buffer = std::mem::replace(
+        &mut *buffer
+            .lock()
+            .map_err(|_| anyhow!("Output buffer lock poisoned"))?,
+        OutputBuffer::new(0),
+    );
+    let output = match master {
+        Some(_) => pty::clean_transcript(&buffer.into_string()),
+        None => buffer.into_string(),
+    };
+
+    // Ctrl-C pressed while the command had the terminal only shows in its status
+    if status.signal() == Some(libc::SIGINT) || status.code() == Some(130) {
+        termination.get_or_insert(Termination::Interrupted);
+    }
+
+    let cwd_changed = sandbox.is_none() && session.restore()?;
+    Ok(CommandOutput {
+        status,
+        termination,
+        output,
+        cwd_changed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(command: &str, timeout: Option<Duration>) -> CommandOutput {
+        let shell = Shell {
+            kind: ShellKind::Sh,
+            program: "sh".to_string(),
+        };
+        let options = ExecOptions {
+            output_limit: 8000,
+            timeout,
+            stdout_to_stderr: true,
+            pty: false,
+        };
+        let mut session = ShellSession::new().unwrap();
+        execute_command(command, &shell, &mut session, &options, None).unwrap()
+    }
+
+    #[test]
+    fn captures_output_and_status() {
+        let output = run("echo out; echo err >&2; exit 3", None);
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.termination, None);
+        assert!(output.output.contains("out") && output.output.contains("err"));
+    }
+
+    #[test]
+    fn timeout_stops_background_processes_holding_the_output() {
+        let started = Instant::now();
+        let output = run("sleep 6 & echo started", Some(Duration::from_secs(1)));
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(
+            output.termination,
+            Some(Termination::TimedOut(Duration::from_secs(1)))
+        );
+        assert!(output.output.contains("started"));
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::bash::{Connector, RedirectKind, Script, SimpleCommand, Word};
+use crate::shell::ShellKind;
+
+// What a command would do to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileAction {
+    Read,
+    Write,
+    Overwrite,
+    Modify,
+    Delete,
+    Create,
+}
+
+impl FileAction {
+    fn label(self) -> &'static str {
+        match self {
+            FileAction::Read => "read",
+            FileAction::Write => "write",
+            FileAction::Overwrite => "overwrite",
+            FileAction::Modify => "modify",
+            FileAction::Delete => "delete",
+            FileAction::Create => "create",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TouchedPath {
+    pub path: PathBuf,
+    pub action: FileAction,
+}
+
+// Matches a file name against a glob pattern with `*`, `?` and `[...]` classes
+pub fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| glob_match(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && glob_match(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let Some(end) = pattern
+                .iter()
+                .skip(2)
+                .position(|&c| c == ']')
+                .map(|i| i + 2)
+            else {
+                return name.first() == Some(&'[') && glob_match(&pattern[1..], &name[1..]);
+            };
+            let Some(&c) = name.first() else {
+                return false;
+            };
+            let (negated, class) = match pattern[1] {
+                '!' | '^' => (true, &pattern[2..end]),
+                _ => (false, &pattern[1..end]),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    matched |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            matched != negated && glob_match(&pattern[end + 1..], &name[1..])
+        }
+        Some(&p) => name.first() == Some(&p) && glob_match(&pattern[1..], &name[1..]),
+    }
+}
+
+// Expands a glob pattern relative to a directory, like bash does without options
+pub fn expand_glob(pattern: &str, cwd: &Path) -> Vec<String> {
+    let absolute = pattern.starts_with('/');
+    let mut matches = vec![if absolute {
+        PathBuf::from("/")
+    } else {
+        PathBuf::new()
+    }];
+
+    for component in pattern.split('/').filter(|component| !component.is_empty()) {
+        if !component.contains(['*', '?', '[']) {
+            for path in &mut matches {
+                path.push(component);
+            }
+            continue;
+        }
+
+        let component_chars = component.chars().collect::<Vec<_>>();
+        let mut next = Vec::new();
+        for path in &matches {
+            let Ok(entries) = std::fs::read_dir(cwd.join(path)) else {
+                continue;
+            };
+            let mut names = entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+                .filter(|name| !name.starts_with('.') || component.starts_with('.'))
+                .filter(|name| glob_match(&component_chars, &name.chars().collect::<Vec<_>>()))
+                .collect::<Vec<_>>();
+            names.sort();
+            next.extend(names.into_iter().map(|name| path.join(name)));
+        }
+        matches = next;
+    }
+
+    matches
+        .into_iter()
+        .filter(|path| cwd.join(path).exists())
+        .map(|path| path.to_string_lossy().to_string())
+        .collect()
+}
+
+// The file names a word stands for once globs are expanded
+fn word_paths(word: &Word, cwd: &Path) -> Vec<String> {
+    if word.glob {
+        let expanded = expand_glob(&word.text, cwd);
+        if !expanded.is_empty() {
+            return expanded;
+        }
+    }
+    vec![word.text.clone()]
+}
+
+// Arguments that are not options
+fn operands(command: &SimpleCommand) -> Vec<&Word> {
+    let mut past_options = false;
+    command
+        .args()
+        .iter()
+        .filter(|arg| {
+            if past_options {
+                return true;
+            }
+            if arg.text == "--" {
+                past_options = true;
+                return false;
+            }
+            !arg.text.starts_with('-') || arg.text == "-"
+        })
+        .collect()
+}
+
+// Lists the files a simple command would touch, as far as can be told without running it
+fn command_paths(command: &SimpleCommand, cwd: &Path) -> Vec<(String, FileAction)> {
+    let mut paths = Vec::new();
+    let operands = operands(command);
+    let mut add = |words: &[&Word], action: FileAction| {
+        for word in words {
+            for path in word_paths(word, cwd) {
+                paths.push((path, action));
+            }
+        }
+    };
+
+    match command.program() {
+        Some("rm" | "rmdir" | "shred" | "unlink") => add(&operands, FileAction::Delete),
+        Some("touch" | "mkdir") => add(&operands, FileAction::Create),
+        Some("mv") => {
+            if let Some((destination, sources)) = operands.split_last() {
+                add(sources, FileAction::Delete);
+                add(&[destination], FileAction::Write);
+            }
+        }
+        Some("cp" | "ln" | "install") => {
+            if let Some((destination, sources)) = operands.split_last() {
+                add(sources, FileAction::Read);
+                add(&[destination], FileAction::Write);
+            }
+        }
+        Some("chmod" | "chown" | "chgrp") => {
+            add(operands.get(1..).unwrap_or_default(), FileAction::Modify)
+        }
+        Some("truncate") => add(&operands, FileAction::Overwrite),
+        Some("tee") => add(&operands, FileAction::Write),
+        Some("sed" | "perl")
+            if command.args().iter().any(|arg| {
+                arg.text.starts_with('-') && !arg.text.starts_with("--") && arg.text.contains('i')
+            }) =>
+        {
+            add(operands.get(1..).unwrap_or_default(), FileAction::Modify)
+        }
+        Some("grep") => add(operands.get(1..).unwrap_or_default(), FileAction::Read),
+        Some("cat" | "less" | "head" | "tail" | "wc" | "sort" | "diff" | "source" | ".") => {
+            add(&operands, FileAction::Read)
+        }
+        _ => {}
+    }
+
+    for redirect in &command.redirects {
+        let action = match redirect.kind {
+            RedirectKind::Input | RedirectKind::ReadWrite => FileAction::Read,
+            RedirectKind::Append | RedirectKind::OutputAndError { append: true } => {
+                FileAction::Write
+            }
+            RedirectKind::Output | RedirectKind::OutputAndError { append: false } => {
+                FileAction::Overwrite
+            }
+            RedirectKind::Duplicate | RedirectKind::HereDoc | RedirectKind::HereString => continue,
+        };
+        if redirect.target.text.starts_with("/dev/") {
+            continue;
+        }
+        for path in word_paths(&redirect.target, cwd) {
+            paths.push((path, action));
+        }
+    }
+    paths
+}
+
+// Lists the files a command line would touch, relative paths resolved against `cwd`
+pub fn touched_paths(script: &Script, cwd: &Path) -> Vec<TouchedPath> {
+    let mut touched: Vec<TouchedPath> = Vec::new();
+    for command in script.all_commands() {
+        for (path, action) in command_paths(&command, cwd) {
+            let path = cwd.join(path);
+            if !touched
+                .iter()
+                .any(|existing| existing.path == path && existing.action == action)
+            {
+                touched.push(TouchedPath { path, action });
+            }
+        }
+    }
+    touched
+}
+
+fn describe_word(word: &Word, cwd: &Path) -> String {
+    if word.glob {
+        let matches = expand_glob(&word.text, cwd);
+        if matches.is_empty() {
+            "glob, matches nothing and is passed literally".to_string()
+        } else {
+            format!("glob, expands to {}", matches.join(" "))
+        }
+    } else if word.expansion {
+        "expanded by the shell at run time".to_string()
+    } else if word.text.starts_with("--") {
+        "long option".to_string()
+    } else if word.text.starts_with('-') && word.text.len() > 1 {
+        let flags = word.text[1..]
+            .chars()
+            .map(|flag| format!("-{}", flag))
+            .collect::<Vec<_>>();
+        if flags.len() > 1 {
+            format!("options {}", flags.join(" "))
+        } else {
+            "option".to_string()
+        }
+    } else {
+        "argument".to_string()
+    }
+}
+
+fn describe_redirect(kind: RedirectKind, fd: Option<u32>) -> &'static str {
+    match (kind, fd) {
+        (RedirectKind::Input, _) => "reads standard input from",
+        (RedirectKind::Output, Some(2)) => "writes standard error to, overwriting,",
+        (RedirectKind::Output, _) => "writes standard output to, overwriting,",
+        (RedirectKind::Append, Some(2)) => "appends standard error to",
+        (RedirectKind::Append, _) => "appends standard output to",
+        (RedirectKind::OutputAndError { append: false }, _) => {
+            "writes standard output and error to, overwriting,"
+        }
+        (RedirectKind::OutputAndError { append: true }, _) => {
+            "appends standard output and error to"
+        }
+        (RedirectKind::Duplicate, _) => "duplicates the file descriptor onto",
+        (RedirectKind::HereDoc, _) => "reads a here-document ending at",
+        (RedirectKind::HereString, _) => "reads standard input from the string",
+        (RedirectKind::ReadWrite, _) => "opens for reading and writing",
+    }
+}
+
+fn describe_connector(connector: Connector) -> &'static str {
+    match connector {
+        Connector::Pipe => "| pipes its output into the next command",
+        Connector::And => "&& runs the next command only if this one succeeds",
+        Connector::Or => "|| runs the next command only if this one fails",
+        Connector::Sequence => "; then runs the next command",
+        Connector::Background => "& runs in the background",
+    }
+}
+
+// Breaks a command down into its parts without running it. Redirections and touched files
+// are only listed for POSIX shells, whose syntax the parser understands.
+pub fn explain(command: &str, shell: ShellKind, cwd: &Path) -> Result<String> {
+    let script = Script::parse(command)?;
+    let mut explanation = String::new();
+
+    for (index, simple) in script.commands.iter().enumerate() {
+        let program = simple
+            .words
+            .first()
+            .map(|word| word.text.as_str())
+            .unwrap_or("(no program)");
+        writeln!(explanation, "{}. {}", index + 1, program)?;
+        for assignment in &simple.assignments {
+            writeln!(
+                explanation,
+                "   {}: sets an environment variable",
+                assignment.text
+            )?;
+        }
+        for arg in simple.args() {
+            writeln!(explanation, "   {}: {}", arg.text, describe_word(arg, cwd))?;
+        }
+        for redirect in simple.redirects.iter().filter(|_| shell.is_posix()) {
+            let fd = redirect.fd.map(|fd| fd.to_string()).unwrap_or_default();
+            writeln!(
+                explanation,
+                "   {}{}: {} {}",
+                fd,
+                match redirect.kind {
+                    RedirectKind::Input => "<",
+                    RedirectKind::Output => ">",
+                    RedirectKind::Append => ">>",
+                    RedirectKind::OutputAndError { append: false } => "&>",
+                    RedirectKind::OutputAndError { append: true } => "&>>",
+                    RedirectKind::Duplicate => ">&",
+                    RedirectKind::HereDoc => "<<",
+                    RedirectKind::HereString => "<<<",
+                    RedirectKind::ReadWrite => "<>",
+                },
+                describe_redirect(redirect.kind, redirect.fd),
+                redirect.target.text
+            )?;
+        }
+        if let Some(connector) = simple.connector
+            && index + 1 < script.commands.len()
+        {
+            writeln!(explanation, "   {}", describe_connector(connector))?;
+        }
+    }
+
+    for substitution in &script.substitutions {
+        writeln!(
+            explanation,
+            "Runs `{}` first and substitutes its output",
+            substitution
+        )?;
+    }
+
+    if !shell.is_posix() {
+        writeln!(
+            explanation,
+            "Cannot tell which files {} syntax touches",
+            shell
+        )?;
+        return Ok(explanation.trim_end().to_string());
+    }
+
+    let touched = touched_paths(&script, cwd);
+    if touched.is_empty() {
+        writeln!(explanation, "No files are touched directly")?;
+    } else {
+        writeln!(explanation, "Files touched:")?;
+        for path in touched {
+            let state = if path.path.exists() { "exists" } else { "new" };
+            let display = path.path.strip_prefix(cwd).unwrap_or(&path.path);
+            writeln!(
+                explanation,
+                "   {} {} ({})",
+                path.action.label(),
+                display.display(),
+                state
+            )?;
+        }
+    }
+
+    Ok(explanation.trim_end().to_string())
+}
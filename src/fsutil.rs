@@ -0,0 +1,51 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs::{self, DirBuilder},
+    hash::{BuildHasher, Hasher},
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+// Creates a directory in `base` that only the current user can access. Its name ends in
+// random characters, so other users can neither guess it nor create it in advance; an
+// existing directory or symlink with that name is an error rather than being reused.
+pub fn create_private_dir(base: &Path, prefix: &str) -> Result<PathBuf> {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    let dir = base.join(format!("{}-{:016x}", prefix, hasher.finish()));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    Ok(dir)
+}
+
+// Removes a file, symlink or directory tree if it exists
+pub fn remove_path(path: &Path) -> std::io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn private_dirs_are_unique_and_owner_only() {
+        let base = std::env::temp_dir();
+        let first = create_private_dir(&base, "aia-test").unwrap();
+        let second = create_private_dir(&base, "aia-test").unwrap();
+        let mode = std::fs::metadata(&first).unwrap().permissions().mode();
+        let _ = std::fs::remove_dir(&first);
+        let _ = std::fs::remove_dir(&second);
+        assert_ne!(first, second);
+        assert_eq!(mode & 0o777, 0o700);
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    fmt::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::bash::SimpleCommand;
+
+// Number of `git status` entries listed in the context
+const MAX_STATUS_ENTRIES: usize = 20;
+// Number of commit subjects listed in the context
+const RECENT_COMMITS: usize = 5;
+
+// Git options placed before the subcommand that take a value
+const OPTIONS_WITH_VALUE: &[&str] = &[
+    "-c",
+    "-C",
+    "--git-dir",
+    "--work-tree",
+    "--namespace",
+    "--exec-path",
+    "--config-env",
+];
+
+// The subcommand of a git invocation followed by its arguments, without git's own options
+pub fn subcommand(command: &SimpleCommand) -> Option<SimpleCommand> {
+    if command.program() != Some("git") {
+        return None;
+    }
+    let args = command.args();
+    let mut i = 0;
+    while let Some(arg) = args.get(i).filter(|arg| arg.text.starts_with('-')) {
+        i += if OPTIONS_WITH_VALUE.contains(&arg.text.as_str()) {
+            2
+        } else {
+            1
+        };
+    }
+    let words = args.get(i..).filter(|words| !words.is_empty())?.to_vec();
+    Some(SimpleCommand {
+        words,
+        ..SimpleCommand::default()
+    })
+}
+
+// Runs git in a directory, returning its trimmed stdout if it succeeded
+pub fn run(cwd: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then(|| {
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string()
+    })
+}
+
+// Counts the entries of `git status --porcelain` by kind
+fn summarize_status(status: &str) -> String {
+    let (mut staged, mut modified, mut untracked, mut conflicted) = (0, 0, 0, 0);
+    for line in status.lines() {
+        let mut codes = line.chars();
+        let (Some(index), Some(worktree)) = (codes.next(), codes.next()) else {
+            continue;
+        };
+        match (index, worktree) {
+            ('?', '?') => untracked += 1,
+            ('U', _) | (_, 'U') | ('A', 'A') | ('D', 'D') => conflicted += 1,
+            _ => {
+                if index != ' ' {
+                    staged += 1;
+                }
+                if worktree != ' ' {
+                    modified += 1;
+                }
+            }
+        }
+    }
+
+    let parts = [
+        (staged, "staged"),
+        (modified, "modified"),
+        (untracked, "untracked"),
+        (conflicted, "conflicted"),
+    ]
+    .into_iter()
+    .filter(|(count, _)| *count > 0)
+    .map(|(count, label)| format!("{} {}", count, label))
+    .collect::<Vec<_>>();
+    if parts.is_empty() {
+        "clean".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+// Describes the git repository containing `cwd`: branch, upstream, remotes, uncommitted
+// changes and recent commits. Returns None outside of a repository.
+pub fn context(cwd: &Path) -> Option<String> {
+    let root = run(cwd, &["rev-parse", "--show-toplevel"])?;
+    let mut context = format!("Git repository: {}", root);
+
+    let branch = run(cwd, &["branch", "--show-current"]).unwrap_or_default();
+    let branch = if branch.is_empty() {
+        match run(cwd, &["rev-parse", "--short", "HEAD"]) {
+            Some(commit) => format!("detached HEAD at {}", commit),
+            None => "none".to_string(),
+        }
+    } else {
+        branch
+    };
+    let _ = write!(context, "\nBranch: {}", branch);
+    if let Some(upstream) = run(cwd, &["rev-parse", "--abbrev-ref", "@{upstream}"]) {
+        let _ = write!(context, ", tracking {}", upstream);
+        if let Some(counts) = run(
+            cwd,
+            &["rev-list", "--left-right", "--count", "HEAD...@{upstream}"],
+        ) && let Some((ahead, behind)) = counts.split_once('\t')
+        {
+            let _ = write!(context, " ({} ahead, {} behind)", ahead, behind);
+        }
+    } else if run(cwd, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_some() {
+        context.push_str(", no upstream");
+    }
+
+    let remotes = run(cwd, &["remote"]).unwrap_or_default();
+    let remotes = remotes.lines().collect::<Vec<_>>();
+    if !remotes.is_empty() {
+        let _ = write!(context, "\nRemotes: {}", remotes.join(", "));
+    }
+
+    if let Some(status) = run(cwd, &["status", "--porcelain"]) {
+        let _ = write!(context, "\nWorking tree: {}", summarize_status(&status));
+        let entries = status.lines().collect::<Vec<_>>();
+        for entry in entries.iter().take(MAX_STATUS_ENTRIES) {
+            let _ = write!(context, "\n  {}", entry);
+        }
+        if entries.len() > MAX_STATUS_ENTRIES {
+            let _ = write!(
+                context,
+                "\n  ... and {} more",
+                entries.len() - MAX_STATUS_ENTRIES
+            );
+        }
+    }
+
+    if let Some(log) = run(
+        cwd,
+        &["log", &format!("-{}", RECENT_COMMITS), "--format=%h %s"],
+    ) && !log.is_empty()
+    {
+        context.push_str("\nRecent commits:");
+        for commit in log.lines() {
+            let _ = write!(context, "\n  {}", commit);
+        }
+    }
+    Some(context)
+}
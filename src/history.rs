@@ -0,0 +1,93 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+// A command executed in an aia session
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub cwd: String,
+    pub prompt: String,
+    pub command: String,
+    pub exit_code: Option<i32>,
+}
+
+impl HistoryEntry {
+    pub fn new(prompt: &str, command: &str, exit_code: Option<i32>) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            cwd: std::env::current_dir()
+                .map(|cwd| cwd.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            prompt: prompt.to_string(),
+            command: command.to_string(),
+            exit_code,
+        }
+    }
+}
+
+// Retrieves the history file path
+fn get_history_path() -> Result<PathBuf> {
+    let data_dir = dirs::data_dir()
+        .context("Failed to get data directory")?
+        .join("aia");
+    Ok(data_dir.join("history.jsonl"))
+}
+
+// Appends an entry to the history file
+pub fn record(entry: &HistoryEntry) -> Result<()> {
+    let path = get_history_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("Failed to create data directory")?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context("Failed to open history file")?;
+    let line = serde_json::to_string(entry).context("Failed to serialize history entry")?;
+    writeln!(file, "{}", line).context("Failed to write history file")?;
+    Ok(())
+}
+
+// Reads the most recent entries, oldest first
+pub fn read(count: usize) -> Result<Vec<HistoryEntry>> {
+    let path = get_history_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(&path).context("Failed to read history file")?;
+    let entries = contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<HistoryEntry>(line).ok())
+        .collect::<Vec<_>>();
+    let skip = entries.len().saturating_sub(count);
+    Ok(entries.into_iter().skip(skip).collect())
+}
+
+// Describes how long ago an entry was recorded, e.g. "5m ago"
+pub fn format_age(timestamp: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let age = now.saturating_sub(timestamp);
+    match age {
+        0..60 => format!("{}s ago", age),
+        60..3600 => format!("{}m ago", age / 60),
+        3600..86400 => format!("{}h ago", age / 3600),
+        _ => format!("{}d ago", age / 86400),
+    }
+}
@@ -1,20 +1,35 @@
+mod agent;
+mod backend;
+mod bash;
+mod cli;
 mod config;
+mod editor;
+mod exec;
+mod explain;
+mod fsutil;
+mod git;
+mod history;
+mod project;
+mod pty;
+mod response;
+mod risk;
+#[cfg(target_os = "linux")]
+mod sandbox;
+mod shell;
+mod tree;
+mod turn;
+mod undo;
 
-use std::{
-    io::Read,
-    process::{Command, Stdio, exit},
-};
+use std::{io::Read, path::Path, process::exit, time::Duration};
 
 use anyhow::{Context, Result, anyhow};
-use async_openai::{
-    Client,
-    config::OpenAIConfig,
-    types::{
-        ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestSystemMessageArgs,
-        ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs,
-    },
-};
+use backend::{Backend, Message, Usage};
+use cli::{Cli, CliCommand, ConfigAction, OutputFormat, Verbosity};
 use cliclack::{input, intro, outro, select, spinner};
+use response::{Action, AiResponse};
+use risk::RiskLevel;
+use shell::Shell;
+use turn::Turn;
 
 // Retrieves the configuration file path
 fn get_config_path() -> Result<std::path::PathBuf> {
@@ -25,24 +40,29 @@ fn get_config_path() -> Result<std::path::PathBuf> {
     Ok(config_path)
 }
 
-// Gathers AI context information such as the current working directory and file listing
-fn get_ai_context() -> Result<String> {
+// Gathers AI context information such as the shell, current working directory, directory tree,
+// project manifests and git repository
+fn get_ai_context(shell: &Shell, config: &config::Config) -> Result<String> {
     let cwd = std::env::current_dir().context("Failed to get current working directory")?;
     let cwd_str = cwd
         .to_str()
         .ok_or_else(|| anyhow!("Failed to convert current working directory to string"))?;
 
-    let paths = std::fs::read_dir(&cwd).context("Failed to read current working directory")?;
-    let file_names = paths
-        .filter_map(|entry| entry.ok())
-        .filter_map(|entry| entry.file_name().to_str().map(|s| s.to_string()))
-        .collect::<Vec<_>>();
+    let tree = tree::render(&cwd, config.context_depth, config.context_budget)
+        .context("Failed to read current working directory")?;
 
-    let context = format!(
-        "Current directory: {}\nFiles in directory: {}",
-        cwd_str,
-        file_names.join(", ")
+    let mut context = format!(
+        "Shell: {}\nCurrent directory: {}\nDirectory tree (directories end with /, symlinks show their target):\n{}",
+        shell.kind, cwd_str, tree
     );
+    if let Some(project_context) = project::context(&cwd) {
+        context.push('\n');
+        context.push_str(&project_context);
+    }
+    if let Some(git_context) = git::context(&cwd) {
+        context.push('\n');
+        context.push_str(&git_context);
+    }
     Ok(context)
 }
 
@@ -60,203 +80,647 @@ fn get_piped_input() -> anyhow::Result<Option<String>> {
     Ok(Some(buffer))
 }
 
-// Sets up the OpenAI API client
-fn setup_client(config: &config::Config) -> Result<Client<OpenAIConfig>> {
-    if config.openai_token.is_empty() {
-        outro("Please set your OpenAI API key in ~/.config/aia/config.toml")
-            .context("Failed to display outro message")?;
-        exit(1);
+// Sets up the configured LLM backend
+async fn setup_backend(config: &mut config::Config, cli: &Cli) -> Result<Box<dyn Backend>> {
+    // Scripts get an error instead of a prompt
+    if cli.non_interactive {
+        return match config.provider {
+            config::Provider::OpenAI
+                if config.openai_token.is_empty() && config.openai_api_base.is_none() =>
+            {
+                Err(anyhow!("No OpenAI API key set in the config file"))
+            }
+            config::Provider::Anthropic if config.anthropic_token.is_empty() => {
+                Err(anyhow!("No Anthropic API key set in the config file"))
+            }
+            config::Provider::Ollama if config.ollama_model.is_empty() => Err(anyhow!(
+                "No Ollama model set, pass one with --model or set ollama_model"
+            )),
+            _ => backend::from_config(config),
+        };
     }
-    unsafe {
-        std::env::set_var("OPENAI_API_KEY", &config.openai_token);
+
+    match config.provider {
+        config::Provider::OpenAI
+            if config.openai_token.is_empty() && config.openai_api_base.is_none() =>
+        {
+            outro("Please set your OpenAI API key in ~/.config/aia/config.toml")
+                .context("Failed to display outro message")?;
+            exit(1);
+        }
+        config::Provider::Anthropic if config.anthropic_token.is_empty() => {
+            outro("Please set your Anthropic API key in ~/.config/aia/config.toml")
+                .context("Failed to display outro message")?;
+            exit(1);
+        }
+        config::Provider::Ollama if config.ollama_model.is_empty() => {
+            let models = backend::ollama::list_models(&config.ollama_url).await?;
+            if models.is_empty() {
+                outro("No Ollama models installed, pull one with `ollama pull <model>`")
+                    .context("Failed to display outro message")?;
+                exit(1);
+            }
+
+            let mut model_select = select("Pick an Ollama model");
+            for model in &models {
+                model_select = model_select.item(model.clone(), model, "");
+            }
+            config.ollama_model = model_select
+                .interact()
+                .context("Failed to parse model selection")?;
+            backend::from_config(config)
+        }
+        _ => backend::from_config(config),
     }
-    Ok(Client::new())
 }
 
-// Sends a request to OpenAI and extracts the JSON response
+// Extracts the JSON object following the [JSON] marker of an unstructured reply
+fn scrape_json(content: &str) -> Option<String> {
+    let content = content.split("[JSON]").nth(1)?;
+    let json = content
+        .trim()
+        .chars()
+        .skip_while(|c| *c != '{')
+        .collect::<String>();
+    Some(json.trim_end_matches("```").trim_end().to_string())
+}
+
+// Parses a reply into its JSON and the response. Structured replies are plain JSON, others need
+// the JSON scraped out of the text. Servers that ignore the requested format send text even
+// when asked for structured output, so scraping is the fallback for those too.
+fn parse_reply(content: &str, structured: bool) -> Result<(String, AiResponse)> {
+    let direct = if structured {
+        AiResponse::parse(content).map(|response| (content.to_string(), response))
+    } else {
+        Err(anyhow!("The reply does not contain a [JSON] section"))
+    };
+    let (json, mut response) = match (direct, scrape_json(content)) {
+        (Ok(parsed), _) => parsed,
+        (Err(_), Some(json)) => {
+            let response = AiResponse::parse(&json)?;
+            (json, response)
+        }
+        (Err(err), None) => return Err(err),
+    };
+    if response.thought.is_none() {
+        response.thought = scrape_thought(content);
+    }
+    Ok((json, response))
+}
+
+// Lowercases the first character so an error message reads as part of a sentence
+fn lowercase_first(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// Tells the model why its last reply was rejected
+fn correction(err: &anyhow::Error) -> String {
+    format!(
+        "Your last reply was invalid because {}. Reply again using the required response format.",
+        lowercase_first(&format!("{:#}", err))
+    )
+}
+
+// Extracts the reasoning in the [THOUGHT] section of an unstructured reply
+fn scrape_thought(content: &str) -> Option<String> {
+    let thought = content.split("[THOUGHT]").nth(1)?.split("[JSON]").next()?;
+    Some(thought.trim().to_string()).filter(|thought| !thought.is_empty())
+}
+
+// Logs a diagnostic message, on plain stderr in non-interactive mode
+fn log_remark(cli: &Cli, message: impl std::fmt::Display) -> Result<()> {
+    if cli.non_interactive {
+        eprintln!("{}", message);
+    } else {
+        cliclack::log::remark(message)?;
+    }
+    Ok(())
+}
+
+// Logs a warning, on plain stderr in non-interactive mode
+fn log_warning(cli: &Cli, message: impl std::fmt::Display) -> Result<()> {
+    if cli.non_interactive {
+        eprintln!("aia: {}", message);
+    } else {
+        cliclack::log::warning(message)?;
+    }
+    Ok(())
+}
+
+// Logs an error, on plain stderr in non-interactive mode
+fn log_error(cli: &Cli, message: impl std::fmt::Display) -> Result<()> {
+    if cli.non_interactive {
+        eprintln!("aia: {}", message);
+    } else {
+        cliclack::log::error(message)?;
+    }
+    Ok(())
+}
+
+// Sends the conversation to the backend and parses the JSON response.
+// Failed requests are retried with exponential backoff, and invalid replies are kept
+// in the conversation along with a correction so the model can fix them.
 async fn get_ai_response(
-    client: &Client<OpenAIConfig>,
+    backend: &dyn Backend,
     config: &config::Config,
-    messages: &[async_openai::types::ChatCompletionRequestMessage],
-) -> Result<(String, serde_json::Value)> {
-    loop {
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(&config.openai_model)
-            .messages(messages)
-            .build()
-            .context("Failed to create request")?;
-
-        let spinner = spinner();
-        spinner.start("Generating response...");
-        let response = client
-            .chat()
-            .create(request)
-            .await
-            .context("Failed to get OpenAI response")?;
-        spinner.stop("Generated response");
-
-        let choice = response
-            .choices
-            .first()
-            .ok_or_else(|| anyhow!("No choices returned in response"))?;
-        let response_content = match choice
-            .message
-            .content
-            .clone()
-            .ok_or_else(|| anyhow!("Failed to get response content"))?
-            .split("[JSON]")
-            .nth(1)
-        {
-            Some(content) => content.trim().to_string(),
-            None => {
-                cliclack::log::error("No JSON content in response")?;
+    cli: &Cli,
+    messages: &mut Vec<Message>,
+) -> Result<(String, AiResponse, Option<Usage>)> {
+    let max_attempts = config.max_attempts.max(1);
+    let mut retry_delay = Duration::from_millis(config.retry_delay_ms);
+
+    for attempt in 1..=max_attempts {
+        // Scripts get no spinner
+        let spinner = (!cli.non_interactive).then(spinner);
+        if let Some(spinner) = &spinner {
+            spinner.start("Generating response...");
+        }
+        let completion = match backend.complete(messages.as_slice()).await {
+            Ok(completion) => completion,
+            Err(err) if attempt < max_attempts => {
+                match &spinner {
+                    Some(spinner) => spinner.error(format!("Request failed: {:#}", err)),
+                    None => log_warning(cli, format!("Request failed: {:#}", err))?,
+                }
+                log_warning(
+                    cli,
+                    format!(
+                        "Retrying in {:.1}s (attempt {}/{})",
+                        retry_delay.as_secs_f32(),
+                        attempt + 1,
+                        max_attempts
+                    ),
+                )?;
+                tokio::time::sleep(retry_delay).await;
+                retry_delay *= 2;
                 continue;
             }
+            Err(err) => {
+                if let Some(spinner) = &spinner {
+                    spinner.error("Request failed");
+                }
+                return Err(err.context(format!(
+                    "Failed to get AI response after {} attempts",
+                    max_attempts
+                )));
+            }
+        };
+        if let Some(spinner) = &spinner {
+            match completion.usage {
+                Some(usage) if cli.verbosity > Verbosity::Quiet => spinner.stop(format!(
+                    "Generated response ({} prompt + {} completion tokens)",
+                    usage.prompt_tokens, usage.completion_tokens
+                )),
+                _ => spinner.stop("Generated response"),
+            }
+        }
+        if cli.verbosity == Verbosity::Verbose {
+            log_remark(cli, format!("Reply: {}", completion.content))?;
         }
-        .chars()
-        .skip_while(|s| *s != '{')
-        .collect::<String>();
-
-        let trimmed_response_content = response_content.trim_end_matches("```");
-        let response_json = serde_json::from_str::<serde_json::Value>(trimmed_response_content);
 
-        match response_json {
-            Ok(json) => return Ok((response_content, json)),
+        match parse_reply(&completion.content, completion.structured) {
+            Ok((response_content, response)) => {
+                return Ok((response_content, response, completion.usage));
+            }
             Err(err) => {
-                cliclack::log::error(format!("Failed to parse JSON: {}", err))?;
-                println!("Response: {}", response_content);
+                log_error(cli, format!("Invalid response: {:#}", err))?;
+                messages.push(Message::assistant(completion.content));
+                messages.push(Message::user(correction(&err)));
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "The model did not return a valid response after {} attempts",
+        max_attempts
+    ))
+}
+
+// Prints a turn as JSON with `--output json`
+fn emit_turn(cli: &Cli, turn: &Turn) -> Result<()> {
+    if cli.output == OutputFormat::Json {
+        turn.emit()?;
+    }
+    Ok(())
+}
+
+// Prints the result of a non-interactive turn: the turn as JSON, or the given text
+fn print_turn(cli: &Cli, turn: &Turn, text: &str) -> Result<()> {
+    match cli.output {
+        OutputFormat::Json => turn.emit(),
+        OutputFormat::Text => {
+            println!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+// Shows what a command would do without running it
+fn show_explanation(command: &str, shell: &Shell) -> Result<()> {
+    let cwd = std::env::current_dir().context("Failed to get current working directory")?;
+    match explain::explain(command, shell.kind, &cwd) {
+        Ok(explanation) => cliclack::note("Explanation", explanation)?,
+        Err(err) => cliclack::log::warning(format!("Could not explain command: {:#}", err))?,
+    }
+    Ok(())
+}
+
+// Shows a proposed command with its risk and asks the user what to do with it.
+// Returns the selected action and the command, which the user may have edited.
+fn review_command(
+    mut command: String,
+    shell: &Shell,
+    config: &config::Config,
+    cli: &Cli,
+) -> Result<(&'static str, String)> {
+    loop {
+        cliclack::log::info(format!("Command: {}", command))?;
+
+        let assessment = risk::assess(&command, shell.kind, config);
+        let risk_summary = format!(
+            "Risk: {} ({})",
+            assessment.level,
+            assessment.reasons.join(", ")
+        );
+        match assessment.level {
+            RiskLevel::Low => {}
+            RiskLevel::Medium => cliclack::log::warning(&risk_summary)?,
+            RiskLevel::High | RiskLevel::Denied => cliclack::log::error(&risk_summary)?,
+        }
+
+        let auto_approved = config.agent_mode
+            && assessment.level == RiskLevel::Low
+            && agent::is_auto_approved(&command, shell.kind, &config.auto_approve);
+        let selected = if auto_approved {
+            cliclack::log::step("Auto-approved")?;
+            "execute"
+        } else if cli.yes && assessment.level <= RiskLevel::Medium {
+            cliclack::log::step("Approved by --yes")?;
+            "execute"
+        } else if assessment.level == RiskLevel::Denied {
+            select("Pick an action")
+                .item("explain", "Explain", "")
+                .item("edit", "Edit", "")
+                .item("denied", "Follow-up", "command blocked by risk_deny")
+                .item("quit", "Quit", "")
+                .interact()
+                .context("Failed to parse user selection")?
+        } else {
+            let mut prompt = select("Pick an action").item(
+                "execute",
+                "Execute",
+                format!("{} risk", assessment.level),
+            );
+            // The sandbox relies on Linux namespaces
+            if cfg!(target_os = "linux") {
+                prompt = prompt.item(
+                    "sandbox",
+                    "Try in sandbox",
+                    "no network, review changes first",
+                );
+            }
+            prompt
+                .item("explain", "Explain", "")
+                .item("edit", "Edit", "")
+                .item("follow", "Follow-up", "")
+                .item("quit", "Quit", "")
+                .interact()
+                .context("Failed to parse user selection")?
+        };
+
+        match selected {
+            "explain" => {
+                show_explanation(&command, shell)?;
                 continue;
             }
+            "edit" => {
+                match editor::edit_command(&command) {
+                    Ok(edited) if !edited.trim().is_empty() => command = edited.trim().to_string(),
+                    Ok(_) => cliclack::log::warning("Empty command, keeping the previous one")?,
+                    Err(err) => cliclack::log::warning(format!("{:#}", err))?,
+                }
+                continue;
+            }
+            // High-risk commands need the confirmation typed out
+            "execute" if assessment.level == RiskLevel::High => {
+                let confirmation: String =
+                    input("This command is high risk. Type \"yes\" to execute it")
+                        .placeholder("no")
+                        .default_input("no")
+                        .interact()
+                        .context("Failed to parse confirmation")?;
+                if confirmation.trim() == "yes" {
+                    return Ok(("execute", command));
+                }
+                return Ok(("follow", command));
+            }
+            _ => return Ok((selected, command)),
         }
     }
 }
 
-// Executes a command using Bash
-fn execute_command(command: &str) -> Result<()> {
-    let status = Command::new("bash")
-        .arg("-c")
-        .arg(command)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("Failed to execute command")?
-        .wait()?;
+// How commands are executed in this session
+fn exec_options(config: &config::Config, cli: &Cli) -> exec::ExecOptions {
+    exec::ExecOptions {
+        output_limit: config.output_limit,
+        timeout: (config.command_timeout > 0).then(|| Duration::from_secs(config.command_timeout)),
+        stdout_to_stderr: cli.output == OutputFormat::Json,
+        pty: config.pty,
+    }
+}
+
+// Saves the files a command is about to change for `aia undo`
+fn take_snapshot(
+    command: &str,
+    shell: &Shell,
+    level: RiskLevel,
+    config: &config::Config,
+    cli: &Cli,
+) -> Result<()> {
+    if !config.undo {
+        return Ok(());
+    }
+    let show = if cli.non_interactive {
+        cli.verbosity == Verbosity::Verbose
+    } else {
+        cli.verbosity > Verbosity::Quiet
+    };
+    match undo::take(command, shell.kind, level) {
+        Ok(Some(snapshot)) if show => log_remark(
+            cli,
+            format!("Saved {}, run `aia undo` to restore it", snapshot.summary()),
+        ),
+        Ok(_) => Ok(()),
+        Err(err) => log_warning(cli, format!("Cannot undo this command: {:#}", err)),
+    }
+}
 
-    cliclack::log::info(format!("Command executed with status: {}", status))?;
+// Tells the user how an executed command ended
+fn log_command_status(output: &exec::CommandOutput) -> Result<()> {
+    match output.termination {
+        Some(exec::Termination::Interrupted) => cliclack::log::warning("Command interrupted")?,
+        Some(exec::Termination::TimedOut(timeout)) => cliclack::log::warning(format!(
+            "Command timed out after {} seconds",
+            timeout.as_secs()
+        ))?,
+        None => cliclack::log::info(format!("Command executed with status: {}", output.status))?,
+    }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Displays an introduction message
-    intro("AIA Terminal Assistant").context("Failed to start intro message")?;
-    let config_path = get_config_path()?;
-    let config = config::Config::read(&config_path).context("Failed to read config file")?;
-    let client = setup_client(&config)?;
+// Runs a command in a sandbox, shows what it changed and lets the user apply the changes.
+// Returns the message telling the model what happened.
+#[cfg(target_os = "linux")]
+fn run_in_sandbox(
+    command: &str,
+    shell: &Shell,
+    session: &mut exec::ShellSession,
+    config: &config::Config,
+    cli: &Cli,
+) -> Result<String> {
+    let sandbox = sandbox::Sandbox::new()?;
+    let output = exec::execute_command(
+        command,
+        shell,
+        session,
+        &exec_options(config, cli),
+        Some(&sandbox),
+    )?;
+    log_command_status(&output)?;
+
+    let changes = sandbox.changes()?;
+    let outcome = if changes.is_empty() {
+        cliclack::log::info("The command changed no files")?;
+        "It changed no files in the working directory."
+    } else {
+        cliclack::note(
+            "Sandbox changes",
+            sandbox.diff(&changes, config.output_limit)?,
+        )?;
+        let selected = select("Apply these changes to the working directory?")
+            .item("apply", "Apply", "")
+            .item("discard", "Discard", "")
+            .interact()
+            .context("Failed to parse user selection")?;
+        if selected == "apply" {
+            sandbox.apply(&changes)?;
+            cliclack::log::success("Changes applied")?;
+            "The user applied its changes to the working directory."
+        } else {
+            "The user discarded its changes."
+        }
+    };
+    Ok(format!(
+        "{}\nThe command ran in a sandbox without network access. {}",
+        output.to_message(command),
+        outcome
+    ))
+}
+
+// Describes the new context after a command changed the working directory
+fn context_update(
+    output: &exec::CommandOutput,
+    shell: &Shell,
+    config: &config::Config,
+) -> Result<String> {
+    if !output.cwd_changed {
+        return Ok(String::new());
+    }
+    Ok(format!(
+        "\n\nThe working directory changed, later commands run in it.\n{}",
+        get_ai_context(shell, config)?
+    ))
+}
 
-    // Initializes conversation messages
+// Builds the opening messages of a conversation, including piped input if available.
+// Returns the messages and the piped input.
+fn start_conversation(
+    cli: &Cli,
+    config: &config::Config,
+    shell: &Shell,
+) -> Result<(Vec<Message>, Option<String>)> {
+    let context = get_ai_context(shell, config)?;
+    if cli.verbosity == Verbosity::Verbose {
+        log_remark(cli, format!("Context:\n{}", context))?;
+    }
     let mut messages = vec![
-        ChatCompletionRequestSystemMessageArgs::default()
-            .content(include_str!("../system_message.txt"))
-            .build()
-            .context("Failed to build system message")?
-            .into(),
-        ChatCompletionRequestUserMessageArgs::default()
-            .content(get_ai_context()?)
-            .build()
-            .context("Failed to build AI context message")?
-            .into(),
+        Message::system(include_str!("../system_message.txt")),
+        Message::user(context),
     ];
 
-    // Adds piped input to messages if available
-    if let Some(piped_input) = get_piped_input().context("Failed to get piped input")? {
-        messages.push(
-            ChatCompletionRequestUserMessageArgs::default()
-                .content(piped_input)
-                .build()
-                .context("Failed to build piped input message")?
-                .into(),
-        );
+    if config.agent_mode {
+        messages.push(Message::system(agent::AGENT_MESSAGE));
     }
 
-    let args: Vec<String> = std::env::args().collect();
+    let piped_input = get_piped_input()
+        .context("Failed to get piped input")?
+        .filter(|piped_input| !piped_input.trim().is_empty());
+    if let Some(piped_input) = &piped_input {
+        messages.push(Message::user(piped_input.clone()));
+    }
+    Ok((messages, piped_input))
+}
+
+// Runs the conversation with the model, starting with the prompt from the command line if any
+async fn run_session(
+    cli: &Cli,
+    config: &config::Config,
+    backend: &dyn Backend,
+    mut prompt: Option<String>,
+) -> Result<()> {
+    let shell = Shell::detect(config.shell);
+    let mut session = exec::ShellSession::new()?;
+    let (mut messages, piped_input) = start_conversation(cli, config, &shell)?;
+
+    // The prompt that led to the commands executed next, recorded in the history
+    let mut last_input = piped_input
+        .map(|piped_input| piped_input.trim().to_string())
+        .unwrap_or_default();
+
+    // Whether the model should respond to the last command's output without new user input
+    let mut awaiting_follow_up = false;
+    // Number of commands executed in agent mode since the last user input
+    let mut agent_steps = 0;
 
     // Main interaction loop
-    for iteration in 0.. {
-        let input = if args.len() > 1 && iteration == 0 {
-            args[1..].join(" ")
-        } else {
-            input("Input:")
-                .interact()
-                .context("Failed to parse input")?
-        };
+    loop {
+        if !awaiting_follow_up {
+            let input = match prompt.take() {
+                Some(prompt) => prompt,
+                None => input("Input:")
+                    .interact()
+                    .context("Failed to parse input")?,
+            };
 
-        messages.push(
-            ChatCompletionRequestUserMessageArgs::default()
-                .content(input.clone())
-                .build()
-                .context("Failed to build user message")?
-                .into(),
-        );
+            last_input = input.clone();
+            messages.push(Message::user(input));
+            agent_steps = 0;
+        }
+        awaiting_follow_up = false;
 
-        let (response_content, response_json) =
-            get_ai_response(&client, &config, &messages).await?;
+        // A failed response ends only this turn, the conversation goes on with the next input
+        let conversation_len = messages.len();
+        let (response_content, response, usage) =
+            match get_ai_response(backend, config, cli, &mut messages).await {
+                Ok(reply) => reply,
+                Err(err) => {
+                    messages.truncate(conversation_len);
+                    cliclack::log::error(format!("{:#}", err))?;
+                    emit_turn(cli, &Turn::error(&err))?;
+                    continue;
+                }
+            };
 
-        messages.push(
-            ChatCompletionRequestAssistantMessageArgs::default()
-                .content(response_content.clone())
-                .build()
-                .context("Failed to build assistant message")?
-                .into(),
-        );
+        messages.push(Message::assistant(response_content));
+        let mut turn = Turn::new(&response, usage);
 
-        match response_json["type"]
-            .as_str()
-            .ok_or_else(|| anyhow!("Failed to get response type"))?
+        if let Some(thought) = &response.thought
+            && cli.verbosity > Verbosity::Quiet
         {
-            "command" => {
-                let command = response_json["command"]
-                    .as_str()
-                    .ok_or_else(|| anyhow!("Failed to get command"))?;
-                cliclack::log::info(format!("Command: {}", command))?;
-
-                let selected = select("Pick an action")
-                    .item("execute", "Execute", "")
-                    .item("follow", "Follow-up", "")
-                    .item("quit", "Quit", "")
-                    .interact()
-                    .context("Failed to parse user selection")?;
+            cliclack::log::remark(thought)?;
+        }
+
+        match response.action {
+            Action::Command { command: proposed } => {
+                let (selected, command) = review_command(proposed.clone(), &shell, config, cli)?;
+                turn.command = Some(command.clone());
+                turn.risk = Some(risk::assess(&command, shell.kind, config));
+                if selected != "execute" || cli.dry_run {
+                    emit_turn(cli, &turn)?;
+                }
+
+                // Tells the model how the user changed its suggestion
+                let edit_note = if command != proposed {
+                    format!(
+                        "User edited the proposed command `{}` to `{}`.\n",
+                        proposed, command
+                    )
+                } else {
+                    String::new()
+                };
 
                 match selected {
+                    "execute" | "sandbox" if cli.dry_run => {
+                        show_explanation(&command, &shell)?;
+                        messages.push(Message::user(format!(
+                            "{}Dry run: the command was explained to the user but not executed",
+                            edit_note
+                        )));
+                    }
                     "execute" => {
-                        execute_command(command).context("Failed to execute command")?;
+                        let level = turn.risk.as_ref().map_or(RiskLevel::Low, |risk| risk.level);
+                        take_snapshot(&command, &shell, level, config, cli)?;
+                        let mut entry = history::HistoryEntry::new(&last_input, &command, None);
+                        let output = exec::execute_command(
+                            &command,
+                            &shell,
+                            &mut session,
+                            &exec_options(config, cli),
+                            None,
+                        )
+                        .context("Failed to execute command")?;
+                        log_command_status(&output)?;
+                        turn.executed = true;
+                        turn.exit_status = output.status.code();
+                        turn.termination = output.termination.map(exec::Termination::label);
+                        emit_turn(cli, &turn)?;
+
+                        entry.exit_code = output.status.code();
+                        if let Err(err) = history::record(&entry) {
+                            cliclack::log::warning(format!("{:#}", err))?;
+                        }
 
-                        let selected = select("Pick an action")
-                            .item("continue", "Continue", "")
-                            .item("quit", "Quit", "")
-                            .interact()
-                            .context("Failed to parse user selection")?;
+                        if !config.agent_mode {
+                            let selected = select("Pick an action")
+                                .item("continue", "Continue", "")
+                                .item("quit", "Quit", "")
+                                .interact()
+                                .context("Failed to parse user selection")?;
 
-                        if selected == "quit" {
-                            break;
+                            if selected == "quit" {
+                                break;
+                            }
                         }
 
-                        messages.push(
-                            ChatCompletionRequestUserMessageArgs::default()
-                                .content("User executed command")
-                                .build()
-                                .context("Failed to build follow-up message")?
-                                .into(),
-                        );
+                        messages.push(Message::user(format!(
+                            "{}{}{}",
+                            edit_note,
+                            output.to_message(&command),
+                            context_update(&output, &shell, config)?
+                        )));
+                        awaiting_follow_up = true;
+
+                        if config.agent_mode {
+                            agent_steps += 1;
+                            if agent_steps >= config.agent_max_steps {
+                                cliclack::log::warning(format!(
+                                    "Reached the limit of {} agent steps",
+                                    config.agent_max_steps
+                                ))?;
+                                awaiting_follow_up = false;
+                            }
+                        }
+                    }
+                    #[cfg(target_os = "linux")]
+                    "sandbox" => {
+                        let note = run_in_sandbox(&command, &shell, &mut session, config, cli)?;
+                        messages.push(Message::user(format!("{}{}", edit_note, note)));
+                        awaiting_follow_up = true;
                     }
                     "follow" => {
-                        messages.push(
-                            ChatCompletionRequestUserMessageArgs::default()
-                                .content("User did not execute command")
-                                .build()
-                                .context("Failed to build follow-up message")?
-                                .into(),
-                        );
+                        messages.push(Message::user(format!(
+                            "{}User did not execute command",
+                            edit_note
+                        )));
+                    }
+                    "denied" => {
+                        messages.push(Message::user(
+                            "The command was blocked by the user's deny list and was not executed",
+                        ));
                     }
                     "quit" => {
                         break;
@@ -264,24 +728,394 @@ async fn main() -> Result<()> {
                     _ => return Err(anyhow!("Invalid selection")),
                 }
             }
-            "question" => {
-                let question = response_json["question"]
-                    .as_str()
-                    .ok_or_else(|| anyhow!("Failed to get question"))?;
+            Action::Question { question } => {
                 cliclack::log::info(format!("Question: {}", question))?;
+                emit_turn(cli, &turn)?;
             }
-            "answer" => {
-                let answer = response_json["answer"]
-                    .as_str()
-                    .ok_or_else(|| anyhow!("Failed to get answer"))?;
+            Action::Answer { answer } => {
                 cliclack::log::info(format!("Answer: {}", answer))?;
+                emit_turn(cli, &turn)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Answers a prompt without asking anything or drawing any decorations: only the suggested
+// command or the answer is printed on stdout. Commands run only with --yes.
+// Returns the exit code.
+async fn run_non_interactive(
+    cli: &Cli,
+    config: &config::Config,
+    backend: &dyn Backend,
+    prompt: Option<String>,
+) -> Result<i32> {
+    let shell = Shell::detect(config.shell);
+    let mut session = exec::ShellSession::new()?;
+    let (mut messages, piped_input) = start_conversation(cli, config, &shell)?;
+    let last_input = match (prompt, piped_input) {
+        (Some(prompt), _) => {
+            messages.push(Message::user(prompt.clone()));
+            prompt
+        }
+        (None, Some(piped_input)) => piped_input.trim().to_string(),
+        (None, None) => {
+            return Err(anyhow!(
+                "A prompt is required in non-interactive mode, pass it as arguments or on stdin"
+            ));
+        }
+    };
+
+    // Number of commands executed in agent mode
+    let mut agent_steps = 0;
+
+    loop {
+        let (response_content, response, usage) =
+            get_ai_response(backend, config, cli, &mut messages).await?;
+        messages.push(Message::assistant(response_content));
+        let mut turn = Turn::new(&response, usage);
+
+        if let Some(thought) = &response.thought
+            && cli.verbosity == Verbosity::Verbose
+        {
+            log_remark(cli, thought)?;
+        }
+
+        let command = match response.action {
+            Action::Answer { answer } => {
+                print_turn(cli, &turn, &answer)?;
+                return Ok(cli::EXIT_ANSWER);
+            }
+            Action::Question { question } => {
+                print_turn(cli, &turn, &question)?;
+                return Ok(cli::EXIT_QUESTION);
+            }
+            Action::Command { command } => command,
+        };
+
+        let assessment = risk::assess(&command, shell.kind, config);
+        turn.risk = Some(assessment.clone());
+
+        // The explanation goes to stderr, leaving stdout to the command or the JSON turn
+        if cli.dry_run {
+            let cwd = std::env::current_dir().context("Failed to get current working directory")?;
+            match explain::explain(&command, shell.kind, &cwd) {
+                Ok(explanation) => eprintln!("{}", explanation),
+                Err(err) => log_warning(cli, format!("Could not explain command: {:#}", err))?,
+            }
+        }
+        if !cli.yes || cli.dry_run {
+            print_turn(cli, &turn, &command)?;
+            return Ok(cli::EXIT_COMMAND);
+        }
+
+        // Nobody can confirm high-risk commands
+        if assessment.level >= RiskLevel::High {
+            log_warning(
+                cli,
+                format!(
+                    "Not executing {} risk command ({})",
+                    assessment.level,
+                    assessment.reasons.join(", ")
+                ),
+            )?;
+            print_turn(cli, &turn, &command)?;
+            return Ok(cli::EXIT_NOT_EXECUTED);
+        }
+
+        // Echo the command like `set -x` so stdout only carries its output
+        eprintln!("+ {}", command);
+        take_snapshot(&command, &shell, assessment.level, config, cli)?;
+        let mut entry = history::HistoryEntry::new(&last_input, &command, None);
+        let output = exec::execute_command(
+            &command,
+            &shell,
+            &mut session,
+            &exec_options(config, cli),
+            None,
+        )
+        .context("Failed to execute command")?;
+        turn.executed = true;
+        turn.exit_status = output.status.code();
+        turn.termination = output.termination.map(exec::Termination::label);
+        emit_turn(cli, &turn)?;
+        match output.termination {
+            Some(exec::Termination::Interrupted) => log_warning(cli, "Command interrupted")?,
+            Some(exec::Termination::TimedOut(timeout)) => log_warning(
+                cli,
+                format!("Command timed out after {} seconds", timeout.as_secs()),
+            )?,
+            None => {}
+        }
+        entry.exit_code = output.status.code();
+        if let Err(err) = history::record(&entry) {
+            log_warning(cli, format!("{:#}", err))?;
+        }
+
+        let exit_code = if output.status.success() {
+            cli::EXIT_COMMAND
+        } else {
+            cli::EXIT_COMMAND_FAILED
+        };
+        if !config.agent_mode {
+            return Ok(exit_code);
+        }
+
+        agent_steps += 1;
+        if agent_steps >= config.agent_max_steps {
+            log_warning(
+                cli,
+                format!(
+                    "Reached the limit of {} agent steps",
+                    config.agent_max_steps
+                ),
+            )?;
+            return Ok(exit_code);
+        }
+        messages.push(Message::user(format!(
+            "{}{}",
+            output.to_message(&command),
+            context_update(&output, &shell, config)?
+        )));
+    }
+}
+
+// Replaces all but the last characters of a secret with asterisks
+fn mask_secret(secret: &str) -> String {
+    if secret.chars().count() <= 8 {
+        return "*".repeat(secret.chars().count());
+    }
+    let visible = secret
+        .chars()
+        .skip(secret.chars().count() - 4)
+        .collect::<String>();
+    format!("****{}", visible)
+}
+
+// Handles `aia config`
+fn run_config_command(
+    action: ConfigAction,
+    config_path: &Path,
+    profile: Option<&str>,
+) -> Result<()> {
+    match action {
+        ConfigAction::Path => println!("{}", config_path.display()),
+        ConfigAction::Edit => {
+            config::Config::create_default(config_path)?;
+            editor::edit_file(config_path)?;
+            config::Config::read(config_path, profile)
+                .context("The edited config file is invalid")?;
+        }
+        ConfigAction::Show => {
+            let mut config =
+                config::Config::read(config_path, profile).context("Failed to read config file")?;
+            config.openai_token = mask_secret(&config.openai_token);
+            config.anthropic_token = mask_secret(&config.anthropic_token);
+            for value in config.openai_headers.values_mut() {
+                *value = mask_secret(value);
             }
-            _ => {
-                return Err(anyhow!("Unexpected response type"));
+            let contents = toml::to_string(&config).context("Failed to format config")?;
+            println!("# {}\n{}", config_path.display(), contents.trim_end());
+        }
+    }
+    Ok(())
+}
+
+// Handles `aia history`
+fn show_history(count: usize) -> Result<()> {
+    for entry in history::read(count)? {
+        let status = entry
+            .exit_code
+            .map(|code| code.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:>8}  [{}]  {}  # {}",
+            history::format_age(entry.timestamp),
+            status,
+            entry.command,
+            entry.cwd
+        );
+    }
+    Ok(())
+}
+
+// Handles `aia undo`
+fn run_undo() -> Result<()> {
+    let snapshot = undo::undo()?;
+    match &snapshot.git_stash {
+        Some(stash) => println!("Applied git stash {} in {}", stash, snapshot.cwd.display()),
+        None => {
+            for saved in &snapshot.paths {
+                let action = if saved.existed { "Restored" } else { "Removed" };
+                println!("{} {}", action, saved.path.display());
             }
         }
     }
+    println!(
+        "Undid `{}` from {}",
+        snapshot.command,
+        history::format_age(snapshot.timestamp)
+    );
+    Ok(())
+}
 
-    outro("Goodbye!").context("Failed to display outro message")?;
+// Prints the keybinding script of `aia shell-init`
+fn print_shell_init(shell: &str) -> Result<()> {
+    let script = match shell {
+        "bash" => include_str!("../shell/aia.bash"),
+        "zsh" => include_str!("../shell/aia.zsh"),
+        "fish" => include_str!("../shell/aia.fish"),
+        _ => {
+            return Err(anyhow!(
+                "Unsupported shell {}, expected bash, zsh or fish",
+                shell
+            ));
+        }
+    };
+    print!("{}", script);
     Ok(())
 }
+
+// Reads the configuration, sets up the backend and runs a session. Returns the exit code.
+async fn start(cli: &Cli, config_path: &Path, prompt: Option<String>) -> Result<i32> {
+    let mut config = config::Config::read(config_path, cli.profile.as_deref())
+        .context("Failed to read config file")?;
+    if let Some(model) = &cli.model {
+        config.set_model(model.clone());
+    }
+    if cli.shell.is_some() {
+        config.shell = cli.shell;
+    }
+    // `aia run` works towards its goal in agent mode
+    if matches!(cli.command, CliCommand::Run(_)) {
+        config.agent_mode = true;
+    }
+    let backend = setup_backend(&mut config, cli).await?;
+
+    if cli.non_interactive {
+        return run_non_interactive(cli, &config, backend.as_ref(), prompt).await;
+    }
+    run_session(cli, &config, backend.as_ref(), prompt).await?;
+    Ok(0)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("Error: {:#}\n\nRun `aia --help` for usage", err);
+            exit(cli::EXIT_USAGE);
+        }
+    };
+    let config_path = match &cli.config {
+        Some(path) => path.clone(),
+        None => get_config_path()?,
+    };
+
+    let prompt = match &cli.command {
+        CliCommand::Help => {
+            print!("{}", cli::HELP);
+            return Ok(());
+        }
+        CliCommand::Version => {
+            println!("aia {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        CliCommand::Config(action) => {
+            return run_config_command(*action, &config_path, cli.profile.as_deref());
+        }
+        CliCommand::History(count) => return show_history(*count),
+        CliCommand::Undo => return run_undo(),
+        CliCommand::ShellInit(shell) => return print_shell_init(shell),
+        CliCommand::Ask(prompt) | CliCommand::Run(prompt) => prompt.clone(),
+    };
+
+    // Displays an introduction message
+    if !cli.non_interactive {
+        intro("AIA Terminal Assistant").context("Failed to start intro message")?;
+    }
+
+    match start(&cli, &config_path, prompt).await {
+        Ok(exit_code) if cli.non_interactive => exit(exit_code),
+        Ok(_) => {
+            outro("Goodbye!").context("Failed to display outro message")?;
+            Ok(())
+        }
+        // Tools reading JSON get the error in the same format
+        Err(err) if cli.non_interactive && cli.output == OutputFormat::Json => {
+            Turn::error(&err).emit()?;
+            exit(1);
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT_REPLY: &str =
+        "[THOUGHT]\nList the files\n[JSON]\n{\"type\": \"command\", \"command\": \"ls\"}";
+
+    #[test]
+    fn structured_replies_are_parsed_directly() {
+        let json = r#"{"thought": "List", "type": "command", "command": "ls", "question": null, "answer": null}"#;
+        let (content, response) = parse_reply(json, true).unwrap();
+        assert_eq!(content, json);
+        assert_eq!(response.thought.as_deref(), Some("List"));
+    }
+
+    #[test]
+    fn structured_replies_fall_back_to_scraping() {
+        let (content, response) = parse_reply(TEXT_REPLY, true).unwrap();
+        assert_eq!(content, r#"{"type": "command", "command": "ls"}"#);
+        assert_eq!(response.thought.as_deref(), Some("List the files"));
+        assert!(matches!(response.action, Action::Command { ref command } if command == "ls"));
+    }
+
+    #[test]
+    fn structured_errors_are_kept_without_a_json_section() {
+        let err = parse_reply("I cannot help with that", true).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err).split(':').next(),
+            Some("The reply is not valid JSON")
+        );
+    }
+
+    #[test]
+    fn scrapes_json_from_a_code_fence() {
+        let reply = "[THOUGHT]\nCount lines\n[JSON]\n```json\n{\"type\": \"command\", \"command\": \"wc -l\"}\n```\n";
+        assert_eq!(
+            scrape_json(reply).as_deref(),
+            Some(r#"{"type": "command", "command": "wc -l"}"#)
+        );
+        assert_eq!(scrape_thought(reply).as_deref(), Some("Count lines"));
+    }
+
+    #[test]
+    fn scraping_needs_the_json_marker() {
+        let reply = "Here you go: {\"type\": \"answer\", \"answer\": \"yes\"}";
+        assert_eq!(scrape_json(reply), None);
+        assert_eq!(scrape_thought(reply), None);
+        let err = parse_reply(reply, false).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "The reply does not contain a [JSON] section"
+        );
+    }
+
+    #[test]
+    fn empty_thought_sections_are_ignored() {
+        assert_eq!(scrape_thought("[THOUGHT]\n  \n[JSON]{}"), None);
+    }
+
+    #[test]
+    fn corrections_name_the_missing_field() {
+        let err = parse_reply(r#"{"type": "answer", "answer": null}"#, true).unwrap_err();
+        assert_eq!(
+            correction(&err),
+            "Your last reply was invalid because a reply of type `answer` needs a string `answer` field. Reply again using the required response format."
+        );
+    }
+}
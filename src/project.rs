@@ -0,0 +1,388 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Maximum number of scripts, targets, members or services listed per manifest
+const MAX_ITEMS: usize = 20;
+// Longest script command shown next to a script name
+const MAX_SCRIPT_LENGTH: usize = 60;
+
+// Joins the first MAX_ITEMS items, noting how many were left out
+fn list(items: &[String]) -> String {
+    let mut listed = items
+        .iter()
+        .take(MAX_ITEMS)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+    if items.len() > MAX_ITEMS {
+        listed.push_str(&format!(" and {} more", items.len() - MAX_ITEMS));
+    }
+    listed
+}
+
+fn table_keys(table: Option<&toml::Value>) -> Vec<String> {
+    table
+        .and_then(toml::Value::as_table)
+        .map(|table| table.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn toml_str<'a>(value: &'a toml::Value, path: &[&str]) -> Option<&'a str> {
+    path.iter()
+        .try_fold(value, |value, key| value.get(key))?
+        .as_str()
+}
+
+// Reads the first line of a version file such as .nvmrc
+fn version_file(dir: &Path, name: &str) -> Option<String> {
+    let contents = fs::read_to_string(dir.join(name)).ok()?;
+    let version = contents.lines().next()?.trim();
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+fn describe_cargo(dir: &Path, contents: &str) -> Vec<String> {
+    let Ok(manifest) = contents.parse::<toml::Value>() else {
+        return vec!["could not be parsed".to_string()];
+    };
+    let mut facts = Vec::new();
+    if let Some(name) = toml_str(&manifest, &["package", "name"]) {
+        let version = toml_str(&manifest, &["package", "version"]).unwrap_or("");
+        facts.push(
+            format!("package {} {}", name, version)
+                .trim_end()
+                .to_string(),
+        );
+    }
+    if let Some(edition) = toml_str(&manifest, &["package", "edition"]) {
+        facts.push(format!("edition {}", edition));
+    }
+    if let Some(rust_version) = toml_str(&manifest, &["package", "rust-version"]) {
+        facts.push(format!("rust-version {}", rust_version));
+    }
+    if let Some(members) = manifest
+        .get("workspace")
+        .and_then(|workspace| workspace.get("members"))
+        .and_then(toml::Value::as_array)
+    {
+        let members = members
+            .iter()
+            .filter_map(|member| member.as_str().map(str::to_string))
+            .collect::<Vec<_>>();
+        facts.push(format!("workspace members: {}", list(&members)));
+    }
+    if let Some(bins) = manifest.get("bin").and_then(toml::Value::as_array) {
+        let bins = bins
+            .iter()
+            .filter_map(|bin| toml_str(bin, &["name"]).map(str::to_string))
+            .collect::<Vec<_>>();
+        if !bins.is_empty() {
+            facts.push(format!("binaries: {}", list(&bins)));
+        }
+    }
+    let features = table_keys(manifest.get("features"));
+    if !features.is_empty() {
+        facts.push(format!("features: {}", list(&features)));
+    }
+    let toolchain = fs::read_to_string(dir.join("rust-toolchain.toml"))
+        .ok()
+        .and_then(|contents| contents.parse::<toml::Value>().ok())
+        .and_then(|toolchain| toml_str(&toolchain, &["toolchain", "channel"]).map(str::to_string))
+        .or_else(|| version_file(dir, "rust-toolchain"));
+    if let Some(toolchain) = toolchain {
+        facts.push(format!("toolchain {}", toolchain));
+    }
+    facts
+}
+
+fn describe_npm(dir: &Path, contents: &str) -> Vec<String> {
+    let Ok(manifest) = serde_json::from_str::<serde_json::Value>(contents) else {
+        return vec!["could not be parsed".to_string()];
+    };
+    let mut facts = Vec::new();
+    if let Some(name) = manifest["name"].as_str() {
+        facts.push(format!("package {}", name));
+    }
+
+    let package_manager = manifest["packageManager"]
+        .as_str()
+        .map(str::to_string)
+        .or_else(|| {
+            [
+                ("pnpm-lock.yaml", "pnpm"),
+                ("yarn.lock", "yarn"),
+                ("bun.lockb", "bun"),
+                ("bun.lock", "bun"),
+                ("package-lock.json", "npm"),
+            ]
+            .into_iter()
+            .find(|(lockfile, _)| dir.join(lockfile).exists())
+            .map(|(_, manager)| manager.to_string())
+        });
+    if let Some(package_manager) = package_manager {
+        facts.push(format!("package manager {}", package_manager));
+    }
+
+    if let Some(scripts) = manifest["scripts"].as_object() {
+        let scripts = scripts
+            .iter()
+            .map(|(name, command)| {
+                let command = command.as_str().unwrap_or_default();
+                if command.chars().count() > MAX_SCRIPT_LENGTH {
+                    let short = command.chars().take(MAX_SCRIPT_LENGTH).collect::<String>();
+                    format!("{} ({}...)", name, short)
+                } else {
+                    format!("{} ({})", name, command)
+                }
+            })
+            .collect::<Vec<_>>();
+        if !scripts.is_empty() {
+            facts.push(format!("scripts: {}", list(&scripts)));
+        }
+    }
+
+    let workspaces = match &manifest["workspaces"] {
+        serde_json::Value::Array(workspaces) => workspaces.clone(),
+        workspaces => workspaces["packages"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default(),
+    };
+    let workspaces = workspaces
+        .iter()
+        .filter_map(|workspace| workspace.as_str().map(str::to_string))
+        .collect::<Vec<_>>();
+    if !workspaces.is_empty() {
+        facts.push(format!("workspaces: {}", list(&workspaces)));
+    }
+
+    let node = manifest["engines"]["node"]
+        .as_str()
+        .map(str::to_string)
+        .or_else(|| version_file(dir, ".nvmrc"))
+        .or_else(|| version_file(dir, ".node-version"));
+    if let Some(node) = node {
+        facts.push(format!("node {}", node));
+    }
+    facts
+}
+
+fn describe_python(dir: &Path, contents: &str) -> Vec<String> {
+    let Ok(manifest) = contents.parse::<toml::Value>() else {
+        return vec!["could not be parsed".to_string()];
+    };
+    let mut facts = Vec::new();
+    if let Some(name) = toml_str(&manifest, &["project", "name"])
+        .or_else(|| toml_str(&manifest, &["tool", "poetry", "name"]))
+    {
+        facts.push(format!("package {}", name));
+    }
+
+    let tools = table_keys(manifest.get("tool"));
+    let manager = if dir.join("uv.lock").exists() || tools.iter().any(|tool| tool == "uv") {
+        Some("uv")
+    } else if dir.join("poetry.lock").exists() || tools.iter().any(|tool| tool == "poetry") {
+        Some("poetry")
+    } else if dir.join("pdm.lock").exists() || tools.iter().any(|tool| tool == "pdm") {
+        Some("pdm")
+    } else if tools.iter().any(|tool| tool == "hatch") {
+        Some("hatch")
+    } else {
+        None
+    };
+    if let Some(manager) = manager {
+        facts.push(format!("managed with {}", manager));
+    }
+    if let Some(backend) = toml_str(&manifest, &["build-system", "build-backend"]) {
+        facts.push(format!("build backend {}", backend));
+    }
+
+    let python = toml_str(&manifest, &["project", "requires-python"])
+        .or_else(|| toml_str(&manifest, &["tool", "poetry", "dependencies", "python"]))
+        .map(str::to_string)
+        .or_else(|| version_file(dir, ".python-version"));
+    if let Some(python) = python {
+        facts.push(format!("python {}", python));
+    }
+
+    let mut scripts = table_keys(
+        manifest
+            .get("project")
+            .and_then(|project| project.get("scripts")),
+    );
+    scripts.extend(table_keys(
+        manifest
+            .get("tool")
+            .and_then(|tool| tool.get("poetry"))
+            .and_then(|poetry| poetry.get("scripts")),
+    ));
+    if !scripts.is_empty() {
+        facts.push(format!("scripts: {}", list(&scripts)));
+    }
+    // Tools configured in pyproject.toml, such as pytest or ruff, hint at how to test and lint
+    let tools = tools
+        .into_iter()
+        .filter(|tool| !["uv", "poetry", "pdm", "hatch", "setuptools"].contains(&tool.as_str()))
+        .collect::<Vec<_>>();
+    if !tools.is_empty() {
+        facts.push(format!("configured tools: {}", list(&tools)));
+    }
+    facts
+}
+
+fn describe_make(_dir: &Path, contents: &str) -> Vec<String> {
+    let mut targets: Vec<String> = Vec::new();
+    for line in contents.lines() {
+        if line.starts_with(['\t', ' ', '#', '.']) {
+            continue;
+        }
+        let Some((names, rest)) = line.split_once(':') else {
+            continue;
+        };
+        // Skip variable assignments like `CC := gcc` and pattern rules like `%.o: %.c`
+        if rest.starts_with('=') || names.contains(['=', '%', '$']) {
+            continue;
+        }
+        for name in names.split_whitespace() {
+            if !targets.iter().any(|target| target == name) {
+                targets.push(name.to_string());
+            }
+        }
+    }
+    if targets.is_empty() {
+        return Vec::new();
+    }
+    vec![format!("targets: {}", list(&targets))]
+}
+
+fn describe_compose(_dir: &Path, contents: &str) -> Vec<String> {
+    // Lists the keys one level below the top-level `services:` key
+    let mut services = Vec::new();
+    let mut in_services = false;
+    let mut indent = None;
+    for line in contents.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let line_indent = line.len() - trimmed.len();
+        if line_indent == 0 {
+            in_services = trimmed.trim_end() == "services:";
+            continue;
+        }
+        if !in_services || *indent.get_or_insert(line_indent) != line_indent {
+            continue;
+        }
+        if let Some(name) = trimmed.strip_suffix(':').or_else(|| {
+            trimmed
+                .split_once(':')
+                .filter(|(_, rest)| rest.trim().is_empty())
+                .map(|(name, _)| name)
+        }) {
+            services.push(name.trim_matches(['"', '\'']).to_string());
+        }
+    }
+    if services.is_empty() {
+        return Vec::new();
+    }
+    vec![format!("services: {}", list(&services))]
+}
+
+fn describe_go(_dir: &Path, contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (directive, value) = line.trim().split_once(char::is_whitespace)?;
+            match directive {
+                "module" => Some(format!("module {}", value.trim())),
+                "go" => Some(format!("go {}", value.trim())),
+                "toolchain" => Some(format!("toolchain {}", value.trim())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+type Describe = fn(&Path, &str) -> Vec<String>;
+
+// Manifest file names with the kind of project they stand for
+const MANIFESTS: &[(&[&str], &str, Describe)] = &[
+    (&["Cargo.toml"], "Cargo", describe_cargo),
+    (&["package.json"], "npm", describe_npm),
+    (&["pyproject.toml"], "Python", describe_python),
+    (&["go.mod"], "Go", describe_go),
+    (
+        &["GNUmakefile", "makefile", "Makefile"],
+        "Make",
+        describe_make,
+    ),
+    (
+        &[
+            "compose.yaml",
+            "compose.yml",
+            "docker-compose.yaml",
+            "docker-compose.yml",
+        ],
+        "Docker Compose",
+        describe_compose,
+    ),
+];
+
+// Writes a path relative to `cwd`, which is one of its descendants
+fn relative_to(path: &Path, cwd: &Path) -> String {
+    let levels = cwd
+        .ancestors()
+        .take_while(|dir| !path.starts_with(dir))
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in 0..levels {
+        relative.push("..");
+    }
+    if let Some(dir) = cwd.ancestors().nth(levels)
+        && let Ok(rest) = path.strip_prefix(dir)
+    {
+        relative.push(rest);
+    }
+    relative.to_string_lossy().to_string()
+}
+
+// Summarizes the project manifests in `cwd` and its ancestors, up to the root of the git
+// repository or the home directory, such as scripts, targets, workspace members and toolchain
+// versions. Returns None if there are none.
+pub fn context(cwd: &Path) -> Option<String> {
+    let home = dirs::home_dir();
+    let mut lines = Vec::new();
+    for dir in cwd.ancestors() {
+        // The home directory is only looked at when it is the cwd
+        if home.as_deref() == Some(dir) && dir != cwd {
+            break;
+        }
+        for (names, kind, describe) in MANIFESTS {
+            let Some(path) = names
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|path| path.is_file())
+            else {
+                continue;
+            };
+            let Ok(contents) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let facts = describe(dir, &contents);
+            let mut line = format!("- {} ({})", kind, relative_to(&path, cwd));
+            if !facts.is_empty() {
+                line.push_str(": ");
+                line.push_str(&facts.join("; "));
+            }
+            lines.push(line);
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    Some(format!("Projects:\n{}", lines.join("\n")))
+}
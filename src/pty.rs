@@ -0,0 +1,190 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{Result, anyhow};
+
+// A pseudo-terminal pair: the command gets the slave side, aia reads and writes the master side
+pub struct Pty {
+    pub master: OwnedFd,
+    pub slave: OwnedFd,
+}
+
+impl Pty {
+    // Opens a pseudo-terminal with the size of the terminal on `terminal`
+    pub fn open(terminal: RawFd) -> Result<Pty> {
+        let mut master = -1;
+        let mut slave = -1;
+        let size = window_size(terminal);
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                size.as_ref()
+                    .map_or(std::ptr::null(), |size| size as *const _),
+            )
+        };
+        if result != 0 {
+            return Err(anyhow!(
+                "Failed to open a pseudo-terminal: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        unsafe {
+            Ok(Pty {
+                master: OwnedFd::from_raw_fd(master),
+                slave: OwnedFd::from_raw_fd(slave),
+            })
+        }
+    }
+}
+
+// Gives the pseudo-terminal the current size of the real terminal if it changed
+pub fn sync_size(master: &OwnedFd, terminal: RawFd, last: &mut Option<(u16, u16)>) {
+    let Some(size) = window_size(terminal) else {
+        return;
+    };
+    if *last != Some((size.ws_row, size.ws_col)) {
+        *last = Some((size.ws_row, size.ws_col));
+        unsafe {
+            libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size);
+        }
+    }
+}
+
+fn window_size(fd: RawFd) -> Option<libc::winsize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+    (result == 0 && size.ws_col > 0).then_some(size)
+}
+
+// Puts a terminal in raw mode, so keys like Ctrl-C reach the command, until dropped
+pub struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable(fd: RawFd) -> Result<RawMode> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(anyhow!(
+                "Failed to read terminal settings: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        let mut raw = original;
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            libc::tcsetattr(fd, libc::TCSANOW, &raw);
+        }
+        Ok(RawMode { fd, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+// Copies keystrokes from stdin to the command until stopped. Polls so that no input
+// meant for aia is consumed once the command has exited.
+pub fn forward_input(master: &OwnedFd, stop: Arc<AtomicBool>) -> Result<JoinHandle<()>> {
+    let mut master = File::from(master.try_clone()?);
+    Ok(thread::spawn(move || {
+        let mut chunk = [0; 1024];
+        while !stop.load(Ordering::SeqCst) {
+            let mut poll = libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut poll, 1, 50) } <= 0 {
+                continue;
+            }
+            let read = unsafe {
+                libc::read(
+                    libc::STDIN_FILENO,
+                    chunk.as_mut_ptr() as *mut libc::c_void,
+                    chunk.len(),
+                )
+            };
+            if read <= 0 || master.write_all(&chunk[..read as usize]).is_err() {
+                return;
+            }
+        }
+    }))
+}
+
+// Reads the master side until every process holding the slave side has exited
+pub struct PtyReader(File);
+
+impl PtyReader {
+    pub fn new(master: &OwnedFd) -> Result<PtyReader> {
+        Ok(PtyReader(File::from(master.try_clone()?)))
+    }
+}
+
+impl Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buf) {
+            // Linux reports the closed slave side as EIO instead of end of file
+            Err(err) if err.raw_os_error() == Some(libc::EIO) => Ok(0),
+            result => result,
+        }
+    }
+}
+
+// Removes escape sequences and carriage-return redraws from a terminal transcript,
+// leaving the text as it was last shown on each line
+pub fn clean_transcript(transcript: &str) -> String {
+    let mut text = String::with_capacity(transcript.len());
+    let mut chars = transcript.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: up to BEL or ST
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\r' if chars.peek() == Some(&'\n') => {}
+            // A lone carriage return redraws the line
+            '\r' => {
+                let line_start = text.rfind('\n').map_or(0, |i| i + 1);
+                text.truncate(line_start);
+            }
+            '\x08' => {
+                text.pop();
+            }
+            c if c.is_control() && c != '\n' && c != '\t' => {}
+            c => text.push(c),
+        }
+    }
+    text
+}
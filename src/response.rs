@@ -0,0 +1,111 @@
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+
+// What the model wants to do next
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Action {
+    Command { command: String },
+    Question { question: String },
+    Answer { answer: String },
+}
+
+// A parsed model reply
+#[derive(Debug, Clone, Deserialize)]
+pub struct AiResponse {
+    #[serde(default)]
+    pub thought: Option<String>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+impl AiResponse {
+    // Parses and validates the JSON part of a model reply
+    pub fn parse(json: &str) -> Result<AiResponse> {
+        let value = serde_json::from_str::<serde_json::Value>(json)
+            .context("The reply is not valid JSON")?;
+
+        // serde does not name the offending field, so check the type-specific one first
+        if let Some(kind @ ("command" | "question" | "answer")) =
+            value.get("type").and_then(|kind| kind.as_str())
+            && !value[kind].is_string()
+        {
+            return Err(anyhow!(
+                "A reply of type `{}` needs a string `{}` field",
+                kind,
+                kind
+            ));
+        }
+
+        let response = serde_json::from_value::<AiResponse>(value)
+            .context("The reply does not match the response format")?;
+
+        let (field, value) = match &response.action {
+            Action::Command { command } => ("command", command),
+            Action::Question { question } => ("question", question),
+            Action::Answer { answer } => ("answer", answer),
+        };
+        if value.trim().is_empty() {
+            return Err(anyhow!("The `{}` field must not be empty", field));
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(json: &str) -> String {
+        format!("{:#}", AiResponse::parse(json).unwrap_err())
+    }
+
+    #[test]
+    fn parses_structured_replies_with_null_fields() {
+        let response = AiResponse::parse(
+            r#"{"thought": "Ask first", "type": "question", "command": null, "question": "Which directory?", "answer": null}"#,
+        )
+        .unwrap();
+        assert_eq!(response.thought.as_deref(), Some("Ask first"));
+        assert!(
+            matches!(response.action, Action::Question { ref question } if question == "Which directory?")
+        );
+    }
+
+    #[test]
+    fn parses_replies_without_thought() {
+        let response = AiResponse::parse(r#"{"type": "answer", "answer": "42"}"#).unwrap();
+        assert!(response.thought.is_none());
+        assert!(matches!(response.action, Action::Answer { ref answer } if answer == "42"));
+    }
+
+    #[test]
+    fn names_a_missing_type_specific_field() {
+        assert_eq!(
+            error(r#"{"type": "command", "command": null, "answer": "ls"}"#),
+            "A reply of type `command` needs a string `command` field"
+        );
+        assert_eq!(
+            error(r#"{"type": "question"}"#),
+            "A reply of type `question` needs a string `question` field"
+        );
+    }
+
+    #[test]
+    fn rejects_empty_fields() {
+        assert_eq!(
+            error(r#"{"type": "command", "command": "  "}"#),
+            "The `command` field must not be empty"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_json_and_unknown_types() {
+        assert!(error("{").starts_with("The reply is not valid JSON"));
+        assert!(
+            error(r#"{"type": "shrug"}"#)
+                .starts_with("The reply does not match the response format")
+        );
+    }
+}
@@ -0,0 +1,1030 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::bash::{Connector, RedirectKind, Script, SimpleCommand, Word, is_assignment};
+use crate::config::Config;
+use crate::git;
+use crate::shell::ShellKind;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+    Low,
+    Medium,
+    High,
+    // Matched a deny pattern and must not be executed
+    Denied,
+}
+
+impl fmt::Display for RiskLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            RiskLevel::Low => "low",
+            RiskLevel::Medium => "medium",
+            RiskLevel::High => "high",
+            RiskLevel::Denied => "denied",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+// The risk of a command along with the rules that flagged it
+#[derive(Serialize, Debug, Clone)]
+pub struct Assessment {
+    pub level: RiskLevel,
+    pub reasons: Vec<String>,
+}
+
+impl Assessment {
+    fn flag(&mut self, level: RiskLevel, reason: impl Into<String>) {
+        self.level = self.level.max(level);
+        self.reasons.push(reason.into());
+    }
+}
+
+// Programs that can destroy data or the system regardless of their arguments
+const HIGH_RISK_PROGRAMS: &[(&str, &str)] = &[
+    ("dd", "writes raw data to files or devices"),
+    ("shred", "irrecoverably overwrites files"),
+    ("fdisk", "modifies disk partitions"),
+    ("parted", "modifies disk partitions"),
+    ("wipefs", "erases filesystem signatures"),
+    ("shutdown", "shuts down the system"),
+    ("reboot", "reboots the system"),
+    ("halt", "halts the system"),
+    ("poweroff", "powers off the system"),
+    ("killall", "kills processes by name"),
+    ("pkill", "kills processes by pattern"),
+    ("crontab", "modifies scheduled jobs"),
+];
+
+// Programs that modify files or the environment
+const MEDIUM_RISK_PROGRAMS: &[(&str, &str)] = &[
+    ("rmdir", "deletes directories"),
+    ("mv", "moves or overwrites files"),
+    ("cp", "may overwrite files"),
+    ("ln", "creates or replaces links"),
+    ("truncate", "truncates files"),
+    ("chmod", "changes file permissions"),
+    ("chown", "changes file ownership"),
+    ("chgrp", "changes file group"),
+    ("kill", "terminates processes"),
+    ("apt", "manages system packages"),
+    ("apt-get", "manages system packages"),
+    ("dnf", "manages system packages"),
+    ("yum", "manages system packages"),
+    ("pacman", "manages system packages"),
+    ("brew", "manages system packages"),
+    ("systemctl", "controls system services"),
+    ("docker", "manages containers"),
+    ("ssh", "runs commands on a remote machine"),
+    ("scp", "copies files over the network"),
+    ("rsync", "synchronizes and may delete files"),
+];
+
+// Programs that execute whatever they are fed on standard input
+const INTERPRETERS: &[&str] = &[
+    "sh", "bash", "zsh", "fish", "dash", "ksh", "python", "python3", "perl", "ruby", "node",
+];
+
+// Paths whose modification affects the whole system or the user's home
+const CRITICAL_PATHS: &[&str] = &[
+    "/", "/*", "~", "~/", "~/*", "$HOME", "$HOME/", "/etc", "/usr", "/bin", "/boot", "/var",
+    "/dev", "/lib", ".",
+];
+
+fn has_flag(command: &SimpleCommand, short: char, long: &str) -> bool {
+    command.args().iter().any(|arg| {
+        let text = arg.text.as_str();
+        text == long
+            || (text.starts_with('-') && !text.starts_with("--") && text[1..].contains(short))
+    })
+}
+
+fn has_arg(command: &SimpleCommand, value: &str) -> bool {
+    command.args().iter().any(|arg| arg.text == value)
+}
+
+// A program that runs the rest of its arguments as another command
+struct Wrapper {
+    program: &'static str,
+    // Options that take the following argument as their value
+    options_with_value: &'static [&'static str],
+    // Arguments between the options and the command, such as the duration of `timeout`
+    positionals: usize,
+}
+
+const WRAPPERS: &[Wrapper] = &[
+    Wrapper {
+        program: "sudo",
+        options_with_value: &[
+            "-u",
+            "--user",
+            "-g",
+            "--group",
+            "-h",
+            "--host",
+            "-p",
+            "--prompt",
+            "-C",
+            "--close-from",
+            "-D",
+            "--chdir",
+            "-r",
+            "--role",
+            "-t",
+            "--type",
+            "-U",
+            "--other-user",
+            "-T",
+            "--command-timeout",
+        ],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "doas",
+        options_with_value: &["-u", "-C"],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "pkexec",
+        options_with_value: &["--user"],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "env",
+        options_with_value: &["-u", "--unset", "-C", "--chdir"],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "timeout",
+        options_with_value: &["-s", "--signal", "-k", "--kill-after"],
+        positionals: 1,
+    },
+    Wrapper {
+        program: "nice",
+        options_with_value: &["-n", "--adjustment"],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "ionice",
+        options_with_value: &["-c", "--class", "-n", "--classdata"],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "stdbuf",
+        options_with_value: &["-i", "-o", "-e", "--input", "--output", "--error"],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "time",
+        options_with_value: &["-f", "--format", "-o", "--output"],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "taskset",
+        options_with_value: &[],
+        positionals: 1,
+    },
+    Wrapper {
+        program: "xargs",
+        options_with_value: &[
+            "-a",
+            "--arg-file",
+            "-d",
+            "--delimiter",
+            "-E",
+            "-I",
+            "-L",
+            "--max-lines",
+            "-n",
+            "--max-args",
+            "-P",
+            "--max-procs",
+            "-s",
+            "--max-chars",
+        ],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "exec",
+        options_with_value: &["-a"],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "nohup",
+        options_with_value: &[],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "setsid",
+        options_with_value: &[],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "command",
+        options_with_value: &[],
+        positionals: 0,
+    },
+    Wrapper {
+        program: "builtin",
+        options_with_value: &[],
+        positionals: 0,
+    },
+];
+
+// Shells whose `-c` argument is a command line that can be assessed like any other
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "fish", "dash", "ksh"];
+
+// Whether an option takes the following argument as its value. In a cluster of short
+// options such as `-Eu`, the first one that takes a value consumes the rest of the cluster.
+fn takes_value(option: &str, options_with_value: &[&str]) -> bool {
+    if option.starts_with("--") {
+        return options_with_value.contains(&option);
+    }
+    for (i, c) in option.char_indices().skip(1) {
+        if options_with_value.contains(&format!("-{}", c).as_str()) {
+            return i + c.len_utf8() == option.len();
+        }
+    }
+    false
+}
+
+// Splits arguments into the leading options and everything after them
+fn split_options<'a>(args: &'a [Word], options_with_value: &[&str]) -> (&'a [Word], &'a [Word]) {
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        let text = arg.text.as_str();
+        if text == "--" {
+            return (&args[..i], &args[i + 1..]);
+        }
+        if !text.starts_with('-') || text == "-" {
+            break;
+        }
+        i += if takes_value(text, options_with_value) {
+            2
+        } else {
+            1
+        };
+    }
+    let i = i.min(args.len());
+    (&args[..i], &args[i..])
+}
+
+// The value of one of the given options among the leading options of a command,
+// written as `-c value`, `-cvalue`, `--name value` or `--name=value`
+fn option_value(command: &SimpleCommand, names: &[&str]) -> Option<Word> {
+    let (options, _) = split_options(command.args(), names);
+    for (i, option) in options.iter().enumerate() {
+        let text = option.text.as_str();
+        if names.contains(&text) {
+            return options.get(i + 1).cloned();
+        }
+        for name in names {
+            let attached = if name.starts_with("--") {
+                text.strip_prefix(&format!("{}=", name))
+            } else {
+                text.strip_prefix(name)
+            };
+            if let Some(value) = attached.filter(|value| !value.is_empty()) {
+                return Some(Word {
+                    text: value.to_string(),
+                    ..option.clone()
+                });
+            }
+        }
+    }
+    None
+}
+
+// The command a wrapper program such as `sudo`, `env` or `timeout` runs
+fn wrapped_command(command: &SimpleCommand) -> Option<SimpleCommand> {
+    let program = command.program()?;
+    let wrapper = WRAPPERS.iter().find(|wrapper| wrapper.program == program)?;
+    let (options, rest) = split_options(command.args(), wrapper.options_with_value);
+    // `command -v` and `command -V` only describe the command
+    if program == "command"
+        && options
+            .iter()
+            .any(|option| option.text.contains(['v', 'V']))
+    {
+        return None;
+    }
+    let words = rest
+        .iter()
+        .skip(wrapper.positionals)
+        .skip_while(|word| is_assignment(word))
+        .cloned()
+        .collect::<Vec<_>>();
+    (!words.is_empty()).then(|| SimpleCommand {
+        words,
+        ..SimpleCommand::default()
+    })
+}
+
+// Assesses a command line passed as an argument, e.g. to `bash -c`
+fn assess_inline_script(script: &Word, assessment: &mut Assessment) {
+    if script.expansion {
+        assessment.flag(RiskLevel::Medium, "runs a dynamically built command");
+    }
+    match Script::parse(&script.text) {
+        Ok(script) => assess_script(&script, assessment),
+        Err(err) => assessment.flag(
+            RiskLevel::Medium,
+            format!("runs inline code that could not be parsed ({})", err),
+        ),
+    }
+}
+
+// Flags interpreters running code that is passed inline or through a here-document
+fn assess_interpreter(command: &SimpleCommand, assessment: &mut Assessment) {
+    let Some(program) = command.program() else {
+        return;
+    };
+    let (options, rest) = split_options(command.args(), &["-o", "-O", "-m", "-W", "-X"]);
+    // Values consumed by options such as `-W` may be empty or start with anything
+    let short = |option: &Word, flags: &[char]| {
+        !option.text.starts_with("--")
+            && option
+                .text
+                .strip_prefix('-')
+                .is_some_and(|cluster| cluster.contains(flags))
+    };
+
+    if SHELLS.contains(&program) {
+        if options
+            .iter()
+            .any(|option| short(option, &['c']) || option.text == "--command")
+        {
+            match rest.first() {
+                Some(script) => assess_inline_script(script, assessment),
+                None => assessment.flag(RiskLevel::Medium, "runs inline code"),
+            }
+            return;
+        }
+    } else if options
+        .iter()
+        .any(|option| short(option, &['c', 'e', 'E']) || option.text == "--eval")
+    {
+        assessment.flag(
+            RiskLevel::Medium,
+            format!("runs inline code that cannot be checked ({})", program),
+        );
+        return;
+    }
+
+    let stdin_script = command.redirects.iter().any(|redirect| {
+        matches!(
+            redirect.kind,
+            RedirectKind::HereDoc | RedirectKind::HereString
+        )
+    });
+    if rest.is_empty() && stdin_script {
+        assessment.flag(
+            RiskLevel::Medium,
+            format!("runs code from a here-document ({})", program),
+        );
+    }
+}
+
+// Expands a leading `~`, `$HOME` or `${HOME}` the way the shell would
+fn expand_home(path: &str) -> Option<std::path::PathBuf> {
+    let home = dirs::home_dir()?;
+    let rest = ["~", "$HOME", "${HOME}"]
+        .iter()
+        .find_map(|prefix| path.strip_prefix(prefix))?;
+    match rest {
+        "" => Some(home),
+        _ => rest.strip_prefix('/').map(|relative| home.join(relative)),
+    }
+}
+
+// Flags writing a file, which is riskier when the file already exists. Targets built from
+// other expansions cannot be checked and are assumed to exist.
+fn flag_overwrite(target: &Word, how: &str, assessment: &mut Assessment) {
+    let path = expand_home(&target.text);
+    let exists = match &path {
+        Some(path) => path.exists(),
+        None => target.expansion || std::path::Path::new(&target.text).exists(),
+    };
+    let level = if exists {
+        RiskLevel::High
+    } else {
+        RiskLevel::Medium
+    };
+    assessment.flag(level, format!("overwrites {} {}", target.text, how));
+}
+
+// Flags risky program invocations in a single simple command
+fn assess_command(command: &SimpleCommand, assessment: &mut Assessment) {
+    let Some(program) = command.program() else {
+        return;
+    };
+
+    match program {
+        "sudo" | "doas" | "su" | "pkexec" => {
+            assessment.flag(
+                RiskLevel::High,
+                format!("runs with elevated privileges ({})", program),
+            );
+            // Also assess the command being elevated
+            if program == "su" {
+                if let Some(script) = option_value(command, &["-c", "--command"]) {
+                    assess_inline_script(&script, assessment);
+                }
+            } else if let Some(wrapped) = wrapped_command(command) {
+                assess_command(&wrapped, assessment);
+            }
+        }
+        "rm" => {
+            let recursive =
+                has_flag(command, 'r', "--recursive") || has_flag(command, 'R', "--recursive");
+            let force = has_flag(command, 'f', "--force");
+            if command
+                .args()
+                .iter()
+                .any(|arg| CRITICAL_PATHS.contains(&arg.text.as_str()))
+            {
+                assessment.flag(RiskLevel::High, "deletes a critical path");
+            } else if recursive && force {
+                assessment.flag(
+                    RiskLevel::High,
+                    "deletes files recursively without confirmation (rm -rf)",
+                );
+            } else if recursive {
+                assessment.flag(RiskLevel::High, "deletes directories recursively");
+            } else {
+                assessment.flag(RiskLevel::Medium, "deletes files");
+            }
+        }
+        "chmod" | "chown" | "chgrp" if has_flag(command, 'R', "--recursive") => {
+            assessment.flag(
+                RiskLevel::High,
+                format!("recursively changes permissions ({} -R)", program),
+            );
+        }
+        "chmod" if has_arg(command, "777") || has_arg(command, "a+rwx") => {
+            assessment.flag(RiskLevel::High, "makes files writable by everyone");
+        }
+        "git" => assess_git(command, assessment),
+        "find" if has_arg(command, "-delete") => {
+            assessment.flag(RiskLevel::High, "deletes every file found (find -delete)");
+        }
+        "find" if has_arg(command, "-exec") || has_arg(command, "-execdir") => {
+            assessment.flag(RiskLevel::Medium, "runs a command on every file found");
+        }
+        "sed" | "perl" if has_flag(command, 'i', "--in-place") => {
+            assessment.flag(RiskLevel::Medium, "edits files in place");
+        }
+        "env" if option_value(command, &["-S", "--split-string"]).is_some() => {
+            if let Some(script) = option_value(command, &["-S", "--split-string"]) {
+                assess_inline_script(&script, assessment);
+            }
+        }
+        _ if WRAPPERS.iter().any(|wrapper| wrapper.program == program) => {
+            if program == "exec" {
+                assessment.flag(RiskLevel::Medium, "replaces the shell (exec)");
+            }
+            if let Some(wrapped) = wrapped_command(command) {
+                assess_command(&wrapped, assessment);
+            }
+        }
+        _ if INTERPRETERS.contains(&program) => assess_interpreter(command, assessment),
+        "eval" | "source" | "." => {
+            assessment.flag(
+                RiskLevel::Medium,
+                format!("executes dynamic code ({})", program),
+            );
+        }
+        "pip" | "pip3" | "npm" | "yarn" | "pnpm" | "cargo" | "gem"
+            if has_arg(command, "install") || has_arg(command, "uninstall") =>
+        {
+            let level = if has_flag(command, 'g', "--global") {
+                RiskLevel::High
+            } else {
+                RiskLevel::Medium
+            };
+            assessment.flag(level, format!("installs or removes packages ({})", program));
+        }
+        _ if program.starts_with("mkfs") => {
+            assessment.flag(RiskLevel::High, "formats a filesystem");
+        }
+        _ => {
+            if let Some((_, reason)) = HIGH_RISK_PROGRAMS.iter().find(|(name, _)| *name == program)
+            {
+                assessment.flag(RiskLevel::High, format!("{} ({})", reason, program));
+            } else if let Some((_, reason)) = MEDIUM_RISK_PROGRAMS
+                .iter()
+                .find(|(name, _)| *name == program)
+            {
+                assessment.flag(RiskLevel::Medium, format!("{} ({})", reason, program));
+            }
+        }
+    }
+
+    for redirect in &command.redirects {
+        let target = redirect.target.text.as_str();
+        if target.starts_with("/dev/")
+            && !matches!(
+                target,
+                "/dev/null" | "/dev/stdout" | "/dev/stderr" | "/dev/tty"
+            )
+        {
+            assessment.flag(RiskLevel::High, format!("writes to a device ({})", target));
+        } else if redirect.truncates_file() && target != "/dev/null" {
+            flag_overwrite(&redirect.target, "with a redirection", assessment);
+        } else if redirect.writes_file() && target != "/dev/null" {
+            assessment.flag(RiskLevel::Medium, format!("appends to {}", target));
+        }
+    }
+}
+
+fn assess_git(command: &SimpleCommand, assessment: &mut Assessment) {
+    // Flags are only checked after the subcommand
+    let Some(command) = &git::subcommand(command) else {
+        return;
+    };
+    let subcommand = command.program();
+
+    // Many subcommands such as `git diff` and `git log` can write their output to a file
+    for (i, arg) in command.args().iter().enumerate() {
+        let target = match arg.text.strip_prefix("--output") {
+            Some("") => command.args().get(i + 1).cloned(),
+            Some(value) => value.strip_prefix('=').map(|value| Word {
+                text: value.to_string(),
+                ..arg.clone()
+            }),
+            None => None,
+        };
+        if let Some(target) = target {
+            flag_overwrite(&target, "with --output", assessment);
+        }
+    }
+
+    match subcommand {
+        Some("push")
+            if has_flag(command, 'f', "--force") || has_arg(command, "--force-with-lease") =>
+        {
+            assessment.flag(
+                RiskLevel::High,
+                "force-pushes and may overwrite remote history",
+            );
+        }
+        Some("push") if has_arg(command, "--delete") || has_flag(command, 'd', "--delete") => {
+            assessment.flag(RiskLevel::High, "deletes remote branches");
+        }
+        Some("reset") if has_arg(command, "--hard") => {
+            assessment.flag(
+                RiskLevel::High,
+                "discards uncommitted changes (git reset --hard)",
+            );
+        }
+        Some("clean") if has_flag(command, 'f', "--force") => {
+            assessment.flag(RiskLevel::High, "deletes untracked files (git clean -f)");
+        }
+        Some("checkout" | "restore") if has_arg(command, ".") || has_arg(command, "--") => {
+            assessment.flag(RiskLevel::High, "discards uncommitted changes");
+        }
+        Some("branch") if has_flag(command, 'D', "-D") => {
+            assessment.flag(RiskLevel::High, "force-deletes a branch");
+        }
+        Some("branch")
+            if has_flag(command, 'f', "--force")
+                || has_flag(command, 'M', "-M")
+                || has_flag(command, 'C', "-C") =>
+        {
+            assessment.flag(RiskLevel::High, "overwrites or moves a branch");
+        }
+        Some("branch")
+            if has_flag(command, 'd', "--delete")
+                || has_flag(command, 'm', "--move")
+                || has_flag(command, 'c', "--copy")
+                || has_flag(command, 'u', "--set-upstream-to")
+                || has_arg(command, "--unset-upstream")
+                || has_arg(command, "--edit-description") =>
+        {
+            assessment.flag(RiskLevel::Medium, "deletes, renames or changes a branch");
+        }
+        Some("stash") if has_arg(command, "drop") || has_arg(command, "clear") => {
+            assessment.flag(RiskLevel::High, "deletes stashed changes");
+        }
+        Some(
+            "push" | "rebase" | "merge" | "commit" | "reset" | "checkout" | "switch" | "pull"
+            | "rm" | "mv",
+        ) => {
+            assessment.flag(RiskLevel::Medium, "modifies the git repository");
+        }
+        _ => {}
+    }
+}
+
+// Matches text against a pattern where `*` matches any sequence and `?` any single character
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Flags every simple command of a parsed command line, including command substitutions
+fn assess_script(script: &Script, assessment: &mut Assessment) {
+    for pair in script.commands.windows(2) {
+        if pair[0].connector != Some(Connector::Pipe) {
+            continue;
+        }
+        let Some(interpreter) = pair[1]
+            .program()
+            .filter(|program| INTERPRETERS.contains(program))
+        else {
+            continue;
+        };
+        // Downloads piped into an interpreter run code nobody has reviewed
+        if matches!(pair[0].program(), Some("curl" | "wget")) {
+            assessment.flag(RiskLevel::High, "pipes a download into an interpreter");
+        } else if pair[1].args().iter().all(|arg| arg.text.starts_with('-')) {
+            assessment.flag(
+                RiskLevel::Medium,
+                format!("pipes generated code into an interpreter ({})", interpreter),
+            );
+        }
+    }
+
+    for command in script.all_commands() {
+        assess_command(&command, assessment);
+    }
+}
+
+// The words of a simple command as they would be typed, for matching allow and deny patterns
+fn command_text(command: &SimpleCommand) -> String {
+    command
+        .assignments
+        .iter()
+        .chain(&command.words)
+        .map(|word| word.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Classifies a command by the damage it could do. Only POSIX shell syntax is fully
+// understood, for other shells the redirections and paths are not inferred.
+pub fn assess(command: &str, shell: ShellKind, config: &Config) -> Assessment {
+    let command = command.trim();
+    let mut assessment = Assessment {
+        level: RiskLevel::Low,
+        reasons: Vec::new(),
+    };
+    let script = Script::parse(command);
+
+    // Deny patterns apply to the whole line and to each command chained in it
+    let mut texts = vec![command.to_string()];
+    if let Ok(script) = &script {
+        texts.extend(script.all_commands().iter().map(command_text));
+    }
+    if let Some(pattern) = config
+        .risk_deny
+        .iter()
+        .find(|pattern| texts.iter().any(|text| wildcard_match(pattern, text)))
+    {
+        assessment.flag(
+            RiskLevel::Denied,
+            format!("matches deny pattern `{}`", pattern),
+        );
+        return assessment;
+    }
+
+    // `>` is a comparison in nu and fish substitutions look like subshells, so only the
+    // programs are checked and the line is never covered by an allow pattern
+    if !shell.is_posix() {
+        assessment.flag(RiskLevel::Medium, format!("cannot assess {} syntax", shell));
+        for command in script.iter().flat_map(|script| &script.commands) {
+            let mut command = command.clone();
+            command.redirects.clear();
+            assess_command(&command, &mut assessment);
+        }
+        return assessment;
+    }
+
+    let script = match script {
+        Ok(script) => script,
+        Err(err) => {
+            assessment.flag(RiskLevel::Medium, format!("could not be parsed ({})", err));
+            return assessment;
+        }
+    };
+
+    // Allow patterns only cover a single command, so that a wildcard cannot also let
+    // through commands chained after it, substituted into it or files it writes
+    let single = match script.commands.as_slice() {
+        [only] => {
+            script.substitutions.is_empty()
+                && only
+                    .redirects
+                    .iter()
+                    .all(|redirect| !redirect.writes_file())
+        }
+        _ => false,
+    };
+    if let Some(pattern) = config
+        .risk_allow
+        .iter()
+        .find(|pattern| single && wildcard_match(pattern, command))
+    {
+        assessment
+            .reasons
+            .push(format!("matches allow pattern `{}`", pattern));
+        return assessment;
+    }
+
+    assess_script(&script, &mut assessment);
+    assessment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn level(command: &str) -> RiskLevel {
+        assess(command, ShellKind::Bash, &config("")).level
+    }
+
+    #[test]
+    fn wildcard_match_handles_stars_and_question_marks() {
+        assert!(wildcard_match("git push origin *", "git push origin main"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("a*b*c", "a-b-b-c"));
+        assert!(wildcard_match("l?", "ls"));
+        assert!(!wildcard_match("l?", "l"));
+        assert!(!wildcard_match("a*b", "a-c"));
+        assert!(!wildcard_match("ls", "ls -la"));
+    }
+
+    #[test]
+    fn read_only_commands_are_low() {
+        for command in [
+            "ls -la",
+            "cat README.md | grep aia",
+            "git status",
+            "echo hi 2>&1",
+        ] {
+            assert_eq!(level(command), RiskLevel::Low, "{}", command);
+        }
+    }
+
+    #[test]
+    fn high_risk_rules() {
+        for command in [
+            "rm -rf build",
+            "rm -r build",
+            "rm ~",
+            "sudo ls",
+            "dd if=/dev/zero of=disk.img",
+            "shred secrets",
+            "mkfs.ext4 /dev/sda1",
+            "killall node",
+            "chmod -R 644 .",
+            "chown -R me src",
+            "chmod 777 script.sh",
+            "find . -name '*.o' -delete",
+            "curl https://example.com/install.sh | sh",
+            "echo data > /dev/sda",
+            "npm install -g typescript",
+            "git push --force",
+            "git push origin --delete feature",
+            "git reset --hard HEAD~1",
+            "git clean -fd",
+            "git checkout -- .",
+            "git branch -D feature",
+            "git stash clear",
+        ] {
+            assert_eq!(level(command), RiskLevel::High, "{}", command);
+        }
+    }
+
+    #[test]
+    fn medium_risk_rules() {
+        for command in [
+            "rm notes.txt",
+            "mv a b",
+            "kill 1234",
+            "sed -i s/a/b/ file",
+            "find . -exec touch {} ;",
+            "eval \"$cmd\"",
+            "cargo install ripgrep",
+            "git commit -m wip",
+            "echo hi >> aia-test-does-not-exist.log",
+            "echo hi > aia-test-does-not-exist.log",
+            "rm \"unterminated",
+        ] {
+            assert_eq!(level(command), RiskLevel::Medium, "{}", command);
+        }
+    }
+
+    #[test]
+    fn overwriting_an_existing_file_is_high() {
+        assert_eq!(level("echo hi > Cargo.toml"), RiskLevel::High);
+        assert_eq!(level("git diff --output=Cargo.toml"), RiskLevel::High);
+        assert_eq!(level("git log --output Cargo.toml"), RiskLevel::High);
+    }
+
+    #[test]
+    fn overwriting_files_in_home_is_high() {
+        let home = dirs::home_dir().unwrap();
+        let name = "aia-test-existing-file";
+        std::fs::write(home.join(name), "").unwrap();
+        let levels = [
+            format!("echo x > ~/{}", name),
+            format!("echo x > $HOME/{}", name),
+            format!("echo x > ${{HOME}}/{}", name),
+        ]
+        .map(|command| level(&command));
+        let _ = std::fs::remove_file(home.join(name));
+        assert_eq!(levels, [RiskLevel::High; 3]);
+
+        assert_eq!(level("echo x > ~/aia-test-missing-file"), RiskLevel::Medium);
+        assert_eq!(level("echo x > \"$TARGET\""), RiskLevel::High);
+    }
+
+    #[test]
+    fn option_values_do_not_crash_the_interpreter_rules() {
+        assert_eq!(level("python3 -W '' x.py"), RiskLevel::Low);
+        assert_eq!(level("python3 -m é"), RiskLevel::Low);
+        assert_eq!(level("bash -o '' -c ls"), RiskLevel::Low);
+    }
+
+    #[test]
+    fn wrapped_commands_are_assessed() {
+        for command in [
+            "env rm -rf /",
+            "env -u HOME FOO=bar rm -rf /",
+            "env -S 'rm -rf /'",
+            "timeout 5 rm -rf /",
+            "timeout -s KILL 5 rm -rf /",
+            "nice -n 10 rm -rf /",
+            "nohup rm -rf / &",
+            "command rm -rf .",
+            "stdbuf -oL rm -rf /",
+            "stdbuf -o L rm -rf /",
+            "time rm -rf /",
+            "/usr/bin/time -p rm -rf /",
+            "setsid rm -rf /",
+            "xargs -n 1 rm -rf",
+            "exec rm -rf /",
+            "env timeout 5 nice rm -rf /",
+        ] {
+            assert_eq!(level(command), RiskLevel::High, "{}", command);
+        }
+        assert_eq!(level("command -v rm"), RiskLevel::Low);
+        assert_eq!(level("timeout 5 ls"), RiskLevel::Low);
+    }
+
+    #[test]
+    fn elevated_commands_are_assessed() {
+        let assessment = assess("sudo -u root rm -rf /", ShellKind::Bash, &config(""));
+        assert_eq!(assessment.level, RiskLevel::High);
+        assert!(
+            assessment
+                .reasons
+                .iter()
+                .any(|r| r == "deletes a critical path")
+        );
+
+        let assessment = assess("su -c 'rm -rf /'", ShellKind::Bash, &config(""));
+        assert!(
+            assessment
+                .reasons
+                .iter()
+                .any(|r| r == "deletes a critical path")
+        );
+    }
+
+    #[test]
+    fn inline_shell_scripts_are_assessed() {
+        for command in [
+            "bash -c 'rm -rf ~'",
+            "sh -c \"rm -rf /\"",
+            "bash -lc 'git reset --hard'",
+            "sh -e -c 'ls; rm -rf build'",
+            "bash -c 'echo $(rm -rf /)'",
+            "bash -c \"bash -c 'rm -rf /'\"",
+        ] {
+            assert_eq!(level(command), RiskLevel::High, "{}", command);
+        }
+        assert_eq!(level("bash -c 'ls -la'"), RiskLevel::Low);
+        assert_eq!(level("bash script.sh"), RiskLevel::Low);
+    }
+
+    #[test]
+    fn unparseable_or_opaque_code_is_at_least_medium() {
+        for command in [
+            "bash -c 'rm \"unterminated'",
+            "bash -c \"$CMD\"",
+            "python3 -c 'import shutil; shutil.rmtree(\"/\")'",
+            "perl -e 'unlink glob \"*\"'",
+            "node -e 'require(\"fs\").rmSync(\"/\")'",
+            "bash <<EOF\nrm -rf /\nEOF",
+            "echo 'rm -rf /' | sh",
+        ] {
+            assert!(level(command) >= RiskLevel::Medium, "{}", command);
+        }
+    }
+
+    #[test]
+    fn git_branch_changes_are_flagged() {
+        assert_eq!(level("git branch"), RiskLevel::Low);
+        assert_eq!(level("git branch -a"), RiskLevel::Low);
+        assert_eq!(level("git branch -f main HEAD~3"), RiskLevel::High);
+        assert_eq!(level("git branch -M a b"), RiskLevel::High);
+        assert_eq!(level("git branch -C a b"), RiskLevel::High);
+        assert_eq!(level("git branch -d feature"), RiskLevel::Medium);
+        assert_eq!(level("git branch -m a b"), RiskLevel::Medium);
+        assert_eq!(level("git branch -c a b"), RiskLevel::Medium);
+        assert_eq!(level("git -C repo branch -D feature"), RiskLevel::High);
+        assert_eq!(level("git -c core.pager=cat log"), RiskLevel::Low);
+    }
+
+    #[test]
+    fn allow_patterns_cover_a_single_command_only() {
+        let config = config(r#"risk_allow = ["git push origin *"]"#);
+        assert_eq!(
+            assess("git push origin main", ShellKind::Bash, &config).level,
+            RiskLevel::Low
+        );
+        for command in [
+            "git push origin main; rm -rf ~",
+            "git push origin main && rm -rf ~",
+            "git push origin main\nrm -rf ~",
+            "git push origin $(rm -rf ~)",
+            "git push origin main > Cargo.toml",
+        ] {
+            assert_eq!(
+                assess(command, ShellKind::Bash, &config).level,
+                RiskLevel::High,
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn deny_patterns_match_chained_commands() {
+        let config = config(r#"risk_deny = ["rm -rf *"]"#);
+        assert_eq!(
+            assess("rm -rf /", ShellKind::Bash, &config).level,
+            RiskLevel::Denied
+        );
+        assert_eq!(
+            assess("ls; rm -rf /", ShellKind::Bash, &config).level,
+            RiskLevel::Denied
+        );
+        assert_eq!(
+            assess("echo $(rm -rf /)", ShellKind::Bash, &config).level,
+            RiskLevel::Denied
+        );
+        assert_eq!(
+            assess("ls -la", ShellKind::Bash, &config).level,
+            RiskLevel::Low
+        );
+    }
+
+    #[test]
+    fn other_shells_skip_redirections() {
+        let config = config(r#"risk_allow = ["ls *"]"#);
+        let assessment = assess("ls | where size > 10mb", ShellKind::Nu, &config);
+        assert_eq!(assessment.level, RiskLevel::Medium);
+        assert_eq!(assessment.reasons, ["cannot assess nu syntax"]);
+        assert_eq!(
+            assess("rm -rf build", ShellKind::Fish, &config).level,
+            RiskLevel::High
+        );
+        assert_eq!(
+            assess("ls > 10mb", ShellKind::Bash, &config).level,
+            RiskLevel::Medium
+        );
+    }
+}
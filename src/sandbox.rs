@@ -0,0 +1,378 @@
+use std::{
+    ffi::CString,
+    fmt::Write as _,
+    fs,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+    },
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::fsutil;
+
+// What a sandboxed command did to a path in the working directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+    // A directory that was deleted and created again, hiding everything it contained
+    Replaced,
+}
+
+impl ChangeKind {
+    fn label(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Deleted => "deleted",
+            ChangeKind::Replaced => "replaced",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Change {
+    // Relative to the working directory
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+// Runs a command in a user namespace without network access, with every mount read-only except
+// for an overlay on the working directory. The overlay's upper layer collects the changes,
+// which can be reviewed and then applied to the real working directory.
+pub struct Sandbox {
+    cwd: PathBuf,
+    dir: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new() -> Result<Sandbox> {
+        let cwd = std::env::current_dir().context("Failed to get current working directory")?;
+        if cwd.to_string_lossy().contains([',', ':']) {
+            return Err(anyhow!(
+                "The sandbox does not support directories with `,` or `:` in their path"
+            ));
+        }
+
+        // The overlay's layers must not overlap, so its upper layer cannot live in the cwd
+        let base = [
+            Some(std::env::temp_dir()),
+            Some(PathBuf::from("/dev/shm")),
+            dirs::runtime_dir(),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|base| !base.starts_with(&cwd))
+        .context("No place outside the working directory for the sandbox")?;
+        let dir = fsutil::create_private_dir(&base, "aia-sandbox")
+            .context("Failed to create sandbox directory")?;
+        fs::create_dir(dir.join("upper")).context("Failed to create sandbox directory")?;
+        fs::create_dir(dir.join("work")).context("Failed to create sandbox directory")?;
+        Ok(Sandbox { cwd, dir })
+    }
+
+    fn upper(&self) -> PathBuf {
+        self.dir.join("upper")
+    }
+
+    // Builds the function that moves the command into the sandbox between fork and exec.
+    // Everything is allocated beforehand, as only async-signal-safe calls can be made there.
+    pub fn setup(&self) -> Result<impl FnMut() -> std::io::Result<()> + Send + Sync + 'static> {
+        let uid = unsafe { libc::getuid() };
+        let gid = unsafe { libc::getgid() };
+        let uid_map = format!("{} {} 1", uid, uid).into_bytes();
+        let gid_map = format!("{} {} 1", gid, gid).into_bytes();
+        let cwd = CString::new(self.cwd.as_os_str().as_bytes())?;
+        let overlay_options = CString::new(format!(
+            "lowerdir={},upperdir={},workdir={},userxattr",
+            self.cwd.display(),
+            self.upper().display(),
+            self.dir.join("work").display()
+        ))?;
+        let mounts = read_only_mounts()?;
+
+        Ok(move || unsafe {
+            check(libc::unshare(
+                libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWNET,
+            ))?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", &uid_map)?;
+            write_file(c"/proc/self/gid_map", &gid_map)?;
+
+            // Keep the mounts below from propagating back to the real system
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            check(libc::mount(
+                c"overlay".as_ptr(),
+                cwd.as_ptr(),
+                c"overlay".as_ptr(),
+                0,
+                overlay_options.as_ptr() as *const libc::c_void,
+            ))?;
+            // Mounts that cannot be made read-only, like those of other namespaces, are skipped
+            for (mount_point, flags) in &mounts {
+                libc::mount(
+                    std::ptr::null(),
+                    mount_point.as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | flags,
+                    std::ptr::null(),
+                );
+            }
+            // Enter the overlay instead of the directory below it
+            check(libc::chdir(cwd.as_ptr()))?;
+            Ok(())
+        })
+    }
+
+    // Lists what the command changed, parents before their contents
+    pub fn changes(&self) -> Result<Vec<Change>> {
+        let mut changes = Vec::new();
+        collect_changes(&self.upper(), &self.cwd, Path::new(""), &mut changes)?;
+        Ok(changes)
+    }
+
+    // Shows the changes as a list followed by a unified diff of the changed files
+    pub fn diff(&self, changes: &[Change], limit: usize) -> Result<String> {
+        let mut text = String::new();
+        for change in changes {
+            writeln!(text, "{} {}", change.kind.label(), change.path.display())?;
+        }
+
+        for change in changes {
+            let upper = self.upper().join(&change.path);
+            if !matches!(change.kind, ChangeKind::Added | ChangeKind::Modified)
+                || !upper.is_file()
+                || upper.is_symlink()
+            {
+                continue;
+            }
+            let original = match change.kind {
+                ChangeKind::Modified => self.cwd.join(&change.path),
+                _ => PathBuf::from("/dev/null"),
+            };
+            let output = Command::new("diff")
+                .arg("-u")
+                .arg("--label")
+                .arg(format!("a/{}", change.path.display()))
+                .arg("--label")
+                .arg(format!("b/{}", change.path.display()))
+                .arg(&original)
+                .arg(&upper)
+                .output();
+            if let Ok(output) = output {
+                text.push('\n');
+                text.push_str(String::from_utf8_lossy(&output.stdout).trim_end());
+            }
+            if text.len() > limit {
+                break;
+            }
+        }
+
+        if text.len() > limit {
+            let end = (0..=limit)
+                .rev()
+                .find(|&i| text.is_char_boundary(i))
+                .unwrap_or(0);
+            text.truncate(end);
+            text.push_str("\n[... diff truncated ...]");
+        }
+        Ok(text.trim_end().to_string())
+    }
+
+    // Applies the changes to the real working directory
+    pub fn apply(&self, changes: &[Change]) -> Result<()> {
+        for change in changes {
+            let target = self.cwd.join(&change.path);
+            let source = self.upper().join(&change.path);
+            let context = || format!("Failed to apply change to {}", change.path.display());
+
+            if matches!(change.kind, ChangeKind::Deleted | ChangeKind::Replaced) {
+                fsutil::remove_path(&target).with_context(context)?;
+            }
+            if change.kind == ChangeKind::Deleted {
+                continue;
+            }
+
+            let metadata = fs::symlink_metadata(&source).with_context(context)?;
+            if metadata.is_dir() {
+                fs::create_dir_all(&target).with_context(context)?;
+                fs::set_permissions(&target, metadata.permissions()).with_context(context)?;
+            } else if metadata.is_symlink() {
+                fsutil::remove_path(&target).with_context(context)?;
+                let link = fs::read_link(&source).with_context(context)?;
+                std::os::unix::fs::symlink(link, &target).with_context(context)?;
+            } else {
+                // Copying onto a symlink would write through it
+                if target.is_symlink() {
+                    fsutil::remove_path(&target).with_context(context)?;
+                }
+                fs::copy(&source, &target).with_context(context)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn check(result: libc::c_int) -> std::io::Result<()> {
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+// Writes a small file using only async-signal-safe calls
+unsafe fn write_file(path: &std::ffi::CStr, contents: &[u8]) -> std::io::Result<()> {
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(fd, contents.as_ptr() as *const libc::c_void, contents.len());
+        libc::close(fd);
+        if written < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Decodes the octal escapes, like `\040` for a space, of /proc/self/mountinfo
+fn unescape_mount_path(path: &str) -> Vec<u8> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && i + 3 < bytes.len()
+            && let Ok(byte) = u8::from_str_radix(&path[i + 1..i + 4], 8)
+        {
+            decoded.push(byte);
+            i += 4;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    decoded
+}
+
+// Lists the mount points to make read-only, with the flags a remount has to keep.
+// Kernel filesystems are left alone so that /dev/null and /proc keep working.
+fn read_only_mounts() -> Result<Vec<(CString, libc::c_ulong)>> {
+    let mountinfo =
+        fs::read_to_string("/proc/self/mountinfo").context("Failed to read mount table")?;
+    let mut mounts = Vec::new();
+    for line in mountinfo.lines() {
+        let fields = line.split(' ').collect::<Vec<_>>();
+        let (Some(mount_point), Some(options)) = (fields.get(4), fields.get(5)) else {
+            continue;
+        };
+        let mount_point = unescape_mount_path(mount_point);
+        if [&b"/proc"[..], b"/sys", b"/dev"].iter().any(|prefix| {
+            mount_point.starts_with(prefix)
+                && matches!(mount_point.get(prefix.len()), None | Some(b'/'))
+        }) {
+            continue;
+        }
+
+        let flags = options
+            .split(',')
+            .map(|option| match option {
+                "nosuid" => libc::MS_NOSUID,
+                "nodev" => libc::MS_NODEV,
+                "noexec" => libc::MS_NOEXEC,
+                "noatime" => libc::MS_NOATIME,
+                "nodiratime" => libc::MS_NODIRATIME,
+                "relatime" => libc::MS_RELATIME,
+                _ => 0,
+            })
+            .fold(0, |flags, flag| flags | flag);
+        mounts.push((CString::new(mount_point)?, flags));
+    }
+    Ok(mounts)
+}
+
+fn is_whiteout(metadata: &fs::Metadata) -> bool {
+    metadata.file_type().is_char_device() && metadata.rdev() == 0
+}
+
+fn is_opaque(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut value = [0u8; 1];
+    let size = unsafe {
+        libc::lgetxattr(
+            path.as_ptr(),
+            c"user.overlay.opaque".as_ptr(),
+            value.as_mut_ptr() as *mut libc::c_void,
+            value.len(),
+        )
+    };
+    size == 1 && value[0] == b'y'
+}
+
+// Walks the overlay's upper layer, comparing it with the real directory
+fn collect_changes(
+    upper: &Path,
+    lower: &Path,
+    relative: &Path,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let mut entries = fs::read_dir(upper.join(relative))
+        .context("Failed to read sandbox changes")?
+        .filter_map(|entry| entry.ok())
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = relative.join(entry.file_name());
+        let metadata = entry.metadata().context("Failed to read sandbox changes")?;
+        let existed = fs::symlink_metadata(lower.join(&path)).is_ok();
+
+        if is_whiteout(&metadata) {
+            changes.push(Change {
+                path,
+                kind: ChangeKind::Deleted,
+            });
+            continue;
+        }
+
+        let kind = if !existed {
+            ChangeKind::Added
+        } else if metadata.is_dir() && is_opaque(&upper.join(&path)) {
+            ChangeKind::Replaced
+        } else {
+            ChangeKind::Modified
+        };
+        // Directories only appear in the upper layer because something inside them changed
+        if !(metadata.is_dir() && kind == ChangeKind::Modified) {
+            changes.push(Change {
+                path: path.clone(),
+                kind,
+            });
+        }
+        if metadata.is_dir() {
+            collect_changes(upper, lower, &path, changes)?;
+        }
+    }
+    Ok(())
+}
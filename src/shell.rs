@@ -0,0 +1,83 @@
+use std::{fmt, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+// The shells aia can write and run commands for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShellKind {
+    Bash,
+    Zsh,
+    Fish,
+    Sh,
+    #[serde(alias = "nushell")]
+    Nu,
+}
+
+impl ShellKind {
+    pub fn parse(name: &str) -> Option<ShellKind> {
+        match name {
+            "bash" => Some(ShellKind::Bash),
+            "zsh" => Some(ShellKind::Zsh),
+            "fish" => Some(ShellKind::Fish),
+            "sh" | "dash" | "ash" => Some(ShellKind::Sh),
+            "nu" | "nushell" => Some(ShellKind::Nu),
+            _ => None,
+        }
+    }
+
+    // Whether commands use the POSIX syntax the bash parser understands
+    pub fn is_posix(self) -> bool {
+        matches!(self, ShellKind::Bash | ShellKind::Zsh | ShellKind::Sh)
+    }
+}
+
+impl fmt::Display for ShellKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ShellKind::Bash => "bash",
+            ShellKind::Zsh => "zsh",
+            ShellKind::Fish => "fish",
+            ShellKind::Sh => "sh",
+            ShellKind::Nu => "nu",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// The shell that executes commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shell {
+    pub kind: ShellKind,
+    // Program to run, the path from $SHELL when it is the same kind of shell
+    pub program: String,
+}
+
+impl Shell {
+    // Uses the configured shell if any, otherwise the one in $SHELL, falling back to bash
+    pub fn detect(configured: Option<ShellKind>) -> Shell {
+        let login_shell = std::env::var("SHELL").ok().and_then(|path| {
+            let name = Path::new(&path).file_name()?.to_str()?.to_string();
+            Some((ShellKind::parse(&name)?, path))
+        });
+
+        match (configured, login_shell) {
+            (Some(kind), Some((login_kind, path))) if kind == login_kind => Shell {
+                kind,
+                program: path,
+            },
+            (Some(kind), _) => Shell {
+                kind,
+                program: kind.to_string(),
+            },
+            (None, Some((kind, path))) => Shell {
+                kind,
+                program: path,
+            },
+            (None, None) => Shell {
+                kind: ShellKind::Bash,
+                program: "bash".to_string(),
+            },
+        }
+    }
+}
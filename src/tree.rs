@@ -0,0 +1,382 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::explain::glob_match;
+
+// Files whose patterns keep paths out of the tree, in the syntax of .gitignore
+const IGNORE_FILES: &[&str] = &[".gitignore", ".aiaignore"];
+// Directories with more entries are collapsed into a count, or cut short in the cwd itself
+const MAX_DIR_ENTRIES: usize = 40;
+
+// A pattern of an ignore file
+struct IgnoreRule {
+    // Directory of the ignore file, the pattern is matched against paths relative to it
+    base: PathBuf,
+    components: Vec<Vec<char>>,
+    negated: bool,
+    dir_only: bool,
+    // Patterns with a slash match from the base, others match a name at any depth
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    fn parse(line: &str, base: &Path) -> Option<IgnoreRule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, pattern) = match pattern.strip_suffix('/') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        let anchored = pattern.contains('/');
+        let components = pattern
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(|component| component.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        if components.is_empty() {
+            return None;
+        }
+        Some(IgnoreRule {
+            base: base.to_path_buf(),
+            components,
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        let names = relative
+            .iter()
+            .map(|name| name.to_string_lossy().chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        if self.anchored {
+            match_components(&self.components, &names)
+        } else {
+            names
+                .last()
+                .is_some_and(|name| glob_match(&self.components[0], name))
+        }
+    }
+}
+
+// Matches path components against pattern components, where `**` spans any number of them.
+// A trailing `**` only matches what is inside a directory, like in git, so `foo/**` keeps `foo`.
+fn match_components(pattern: &[Vec<char>], names: &[Vec<char>]) -> bool {
+    match pattern.first() {
+        None => names.is_empty(),
+        Some(component) if component == &['*', '*'] => {
+            let min_skip = usize::from(pattern.len() == 1);
+            (min_skip..=names.len()).any(|skip| match_components(&pattern[1..], &names[skip..]))
+        }
+        Some(component) => {
+            !names.is_empty()
+                && glob_match(component, &names[0])
+                && match_components(&pattern[1..], &names[1..])
+        }
+    }
+}
+
+// Ignore rules in effect for a directory, those of deeper ignore files last
+#[derive(Default)]
+struct Ignore {
+    rules: Vec<IgnoreRule>,
+}
+
+impl Ignore {
+    // Starts with the ignore files of the ancestors of `dir` up to the root of its git
+    // repository, as they apply to the directory too
+    fn for_root(dir: &Path) -> Ignore {
+        let mut ignore = Ignore::default();
+        if let Some(repository) = dir
+            .ancestors()
+            .find(|ancestor| ancestor.join(".git").exists())
+        {
+            let mut ancestors = dir
+                .ancestors()
+                .skip(1)
+                .take_while(|ancestor| ancestor.starts_with(repository))
+                .collect::<Vec<_>>();
+            ancestors.reverse();
+            for ancestor in ancestors {
+                ignore.load(ancestor);
+            }
+        }
+        ignore.load(dir);
+        ignore
+    }
+
+    fn load(&mut self, dir: &Path) {
+        for file in IGNORE_FILES {
+            if let Ok(contents) = fs::read_to_string(dir.join(file)) {
+                self.rules.extend(
+                    contents
+                        .lines()
+                        .filter_map(|line| IgnoreRule::parse(line, dir)),
+                );
+            }
+        }
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+enum Kind {
+    File,
+    Dir,
+    Symlink(PathBuf),
+}
+
+struct Entry {
+    name: String,
+    kind: Kind,
+    // Number of entries of a directory that was read
+    total: usize,
+    // Contents of a directory within the depth limit, unless it was collapsed
+    children: Option<Vec<Entry>>,
+}
+
+// Reads the visible entries of a directory, directories first
+fn read_entries(dir: &Path, ignore: &Ignore) -> Result<Vec<Entry>> {
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let file_type = entry.file_type().ok()?;
+            let kind = if file_type.is_symlink() {
+                Kind::Symlink(fs::read_link(entry.path()).unwrap_or_default())
+            } else if file_type.is_dir() {
+                Kind::Dir
+            } else {
+                Kind::File
+            };
+            let is_dir = matches!(kind, Kind::Dir);
+            if (is_dir && name == ".git") || ignore.is_ignored(&entry.path(), is_dir) {
+                return None;
+            }
+            Some(Entry {
+                name,
+                kind,
+                total: 0,
+                children: None,
+            })
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| {
+        let a_dir = matches!(a.kind, Kind::Dir);
+        let b_dir = matches!(b.kind, Kind::Dir);
+        b_dir.cmp(&a_dir).then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(entries)
+}
+
+// Reads the directories below `dir` down to `depth` levels
+fn walk(dir: &Path, entries: &mut [Entry], ignore: &mut Ignore, depth: usize) {
+    if depth == 0 {
+        return;
+    }
+    for entry in entries.iter_mut() {
+        if !matches!(entry.kind, Kind::Dir) {
+            continue;
+        }
+        let path = dir.join(&entry.name);
+        let rule_count = ignore.rules.len();
+        ignore.load(&path);
+        if let Ok(mut children) = read_entries(&path, ignore) {
+            entry.total = children.len();
+            if children.len() <= MAX_DIR_ENTRIES {
+                walk(&path, &mut children, ignore, depth - 1);
+                entry.children = Some(children);
+            }
+        }
+        ignore.rules.truncate(rule_count);
+    }
+}
+
+// Writes the entries down to `depth` levels, one per line and indented by level
+fn render_entries(entries: &[Entry], indent: usize, depth: usize, out: &mut String) {
+    for entry in entries {
+        out.push_str(&"  ".repeat(indent));
+        out.push_str(&entry.name);
+        match &entry.kind {
+            Kind::File => {}
+            Kind::Symlink(target) => {
+                out.push_str(" -> ");
+                out.push_str(&target.to_string_lossy());
+            }
+            Kind::Dir => {
+                out.push('/');
+                match &entry.children {
+                    Some(children) if depth > 1 => {
+                        out.push('\n');
+                        render_entries(children, indent + 1, depth - 1, out);
+                        continue;
+                    }
+                    None if entry.total > MAX_DIR_ENTRIES => {
+                        out.push_str(&format!(" ({} entries)", entry.total));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        out.push('\n');
+    }
+}
+
+// Lists the contents of `dir` as an indented tree, ignoring paths matched by .gitignore and
+// .aiaignore files. The tree goes as many levels deep, up to `max_depth`, as fits in `budget`
+// characters; if even the first level does not fit, it is cut short.
+pub fn render(dir: &Path, max_depth: usize, budget: usize) -> Result<String> {
+    let mut ignore = Ignore::for_root(dir);
+    let mut entries = read_entries(dir, &ignore)?;
+    let hidden = entries.len().saturating_sub(MAX_DIR_ENTRIES);
+    entries.truncate(MAX_DIR_ENTRIES);
+    walk(dir, &mut entries, &mut ignore, max_depth.saturating_sub(1));
+
+    let more = if hidden > 0 {
+        format!("... and {} more entries\n", hidden)
+    } else {
+        String::new()
+    };
+    for depth in (1..=max_depth.max(1)).rev() {
+        let mut tree = String::new();
+        render_entries(&entries, 0, depth, &mut tree);
+        tree.push_str(&more);
+        if tree.len() <= budget || depth == 1 {
+            if tree.len() > budget {
+                let mut end = budget;
+                while !tree.is_char_boundary(end) {
+                    end -= 1;
+                }
+                let end = tree[..end].rfind('\n').map_or(0, |end| end + 1);
+                tree.truncate(end);
+                tree.push_str("... (listing cut short)\n");
+            }
+            return Ok(tree.trim_end().to_string());
+        }
+    }
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsutil;
+
+    // Creates a temporary directory with the given files, names ending in `/` are directories
+    fn fixture(paths: &[(&str, &str)]) -> PathBuf {
+        let dir = fsutil::create_private_dir(&std::env::temp_dir(), "aia-test").unwrap();
+        for (path, contents) in paths {
+            let path = dir.join(path);
+            if path.to_string_lossy().ends_with('/') {
+                fs::create_dir_all(&path).unwrap();
+            } else {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, contents).unwrap();
+            }
+        }
+        dir
+    }
+
+    fn render_fixture(paths: &[(&str, &str)], max_depth: usize, budget: usize) -> String {
+        let dir = fixture(paths);
+        let tree = render(&dir, max_depth, budget);
+        let _ = fs::remove_dir_all(&dir);
+        tree.unwrap()
+    }
+
+    #[test]
+    fn follows_gitignore_rules() {
+        let tree = render_fixture(
+            &[
+                (
+                    ".gitignore",
+                    "# build output\n*.log\n!keep.log\n/build\ntarget/\ndocs/**\nsrc/**/gen\n",
+                ),
+                ("a.log", ""),
+                ("keep.log", ""),
+                ("build/out", ""),
+                ("target", ""),
+                ("docs/guide.md", ""),
+                ("src/main.rs", ""),
+                ("src/gen", ""),
+                ("src/a/b/gen", ""),
+                ("sub/.gitignore", "!a.log\n"),
+                ("sub/a.log", ""),
+                ("sub/build/out", ""),
+                ("sub/target/", ""),
+            ],
+            4,
+            10_000,
+        );
+        assert_eq!(
+            tree,
+            "docs/\n\
+             src/\n  a/\n    b/\n  main.rs\n\
+             sub/\n  build/\n    out\n  .gitignore\n  a.log\n\
+             .gitignore\nkeep.log\ntarget"
+        );
+    }
+
+    #[test]
+    fn trailing_double_star_keeps_the_directory() {
+        let rule = IgnoreRule::parse("foo/**", Path::new("/repo")).unwrap();
+        assert!(!rule.matches(Path::new("/repo/foo"), true));
+        assert!(rule.matches(Path::new("/repo/foo/bar"), false));
+        assert!(rule.matches(Path::new("/repo/foo/bar/baz"), true));
+
+        let rule = IgnoreRule::parse("**/foo", Path::new("/repo")).unwrap();
+        assert!(rule.matches(Path::new("/repo/foo"), true));
+        assert!(rule.matches(Path::new("/repo/a/b/foo"), false));
+    }
+
+    #[test]
+    fn shrinks_the_tree_to_the_budget() {
+        let paths = [("a/b/c", ""), ("a/d", ""), ("e", "")];
+        assert_eq!(render_fixture(&paths, 3, 100), "a/\n  b/\n    c\n  d\ne");
+        assert_eq!(render_fixture(&paths, 3, 15), "a/\n  b/\n  d\ne");
+        assert_eq!(render_fixture(&paths, 3, 5), "a/\ne");
+        assert_eq!(render_fixture(&paths, 3, 3), "a/\n... (listing cut short)");
+    }
+
+    #[test]
+    fn collapses_large_directories() {
+        let names = (0..45)
+            .map(|index| format!("big/{:02}", index))
+            .chain((0..45).map(|index| format!("{:02}", index)))
+            .collect::<Vec<_>>();
+        let paths = names
+            .iter()
+            .map(|name| (name.as_str(), ""))
+            .collect::<Vec<_>>();
+        let tree = render_fixture(&paths, 2, 10_000);
+        let lines = tree.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "big/ (45 entries)");
+        assert_eq!(lines.len(), MAX_DIR_ENTRIES + 1);
+        assert_eq!(lines[MAX_DIR_ENTRIES - 1], "38");
+        assert_eq!(lines[MAX_DIR_ENTRIES], "... and 6 more entries");
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::backend::Usage;
+use crate::response::{Action, AiResponse};
+use crate::risk::Assessment;
+
+// A single model reply and what became of it, printed as one line of JSON with `--output json`
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Turn {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub thought: Option<String>,
+    pub command: Option<String>,
+    pub question: Option<String>,
+    pub answer: Option<String>,
+    pub risk: Option<Assessment>,
+    pub usage: Option<Usage>,
+    pub executed: bool,
+    // Exit code of the executed command, null if it was terminated by a signal
+    pub exit_status: Option<i32>,
+    // "interrupted" or "timed_out" if the command was stopped
+    pub termination: Option<&'static str>,
+    pub error: Option<String>,
+}
+
+impl Turn {
+    pub fn new(response: &AiResponse, usage: Option<Usage>) -> Self {
+        let mut turn = Turn {
+            thought: response.thought.clone(),
+            usage,
+            ..Turn::default()
+        };
+        match &response.action {
+            Action::Command { command } => {
+                turn.kind = "command";
+                turn.command = Some(command.clone());
+            }
+            Action::Question { question } => {
+                turn.kind = "question";
+                turn.question = Some(question.clone());
+            }
+            Action::Answer { answer } => {
+                turn.kind = "answer";
+                turn.answer = Some(answer.clone());
+            }
+        }
+        turn
+    }
+
+    pub fn error(err: &anyhow::Error) -> Self {
+        Turn {
+            kind: "error",
+            error: Some(format!("{:#}", err)),
+            ..Turn::default()
+        }
+    }
+
+    // Prints the turn as a single line of JSON on stdout
+    pub fn emit(&self) -> Result<()> {
+        let line = serde_json::to_string(self).context("Failed to serialize turn")?;
+        println!("{}", line);
+        Ok(())
+    }
+}
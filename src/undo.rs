@@ -0,0 +1,395 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::bash::{Script, SimpleCommand};
+use crate::explain::{self, FileAction};
+use crate::fsutil;
+use crate::git;
+use crate::risk::RiskLevel;
+use crate::shell::ShellKind;
+
+// Snapshots larger than this are not taken
+const SNAPSHOT_LIMIT: u64 = 100 * 1024 * 1024;
+// Number of snapshots kept, older ones are removed
+const MAX_SNAPSHOTS: usize = 20;
+
+// A path saved before a command ran
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedPath {
+    pub path: PathBuf,
+    // Paths that did not exist are removed on undo
+    pub existed: bool,
+}
+
+// What `aia undo` restores: copies of the paths a command was expected to change, and a git
+// stash of the working tree for git commands that discard uncommitted changes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub cwd: PathBuf,
+    pub command: String,
+    #[serde(default)]
+    pub paths: Vec<SavedPath>,
+    // Commit created by `git stash create`
+    #[serde(default)]
+    pub git_stash: Option<String>,
+}
+
+impl Snapshot {
+    pub fn summary(&self) -> String {
+        let copies = match self.paths.as_slice() {
+            [] => None,
+            [saved] => Some(format!("a copy of {}", saved.path.display())),
+            paths => Some(format!("a copy of {} paths", paths.len())),
+        };
+        match (&self.git_stash, copies) {
+            (Some(_), Some(copies)) => format!("a git stash of the working tree and {}", copies),
+            (Some(_), None) => "a git stash of the working tree".to_string(),
+            (None, copies) => copies.unwrap_or_default(),
+        }
+    }
+}
+
+// Retrieves the directory holding one subdirectory per snapshot
+fn get_undo_dir() -> Result<PathBuf> {
+    let data_dir = dirs::data_dir()
+        .context("Failed to get data directory")?
+        .join("aia");
+    Ok(data_dir.join("undo"))
+}
+
+// Lists the snapshot directories, oldest first
+fn snapshot_dirs() -> Result<Vec<PathBuf>> {
+    let undo_dir = get_undo_dir()?;
+    if !undo_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut dirs = fs::read_dir(&undo_dir)
+        .context("Failed to read undo directory")?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join("snapshot.json").is_file())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    Ok(dirs)
+}
+
+// The paths under `cwd` a command would create, change or delete
+fn modified_paths(command: &str, cwd: &Path) -> Vec<PathBuf> {
+    let Ok(script) = Script::parse(command) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for touched in explain::touched_paths(&script, cwd) {
+        let inside_cwd = touched.path.starts_with(cwd)
+            && !touched
+                .path
+                .components()
+                .any(|component| component == Component::ParentDir);
+        if touched.action != FileAction::Read && inside_cwd && !paths.contains(&touched.path) {
+            paths.push(touched.path);
+        }
+    }
+    paths
+}
+
+// Git commands that overwrite the working tree in ways the parser cannot tell the paths of
+#[derive(Default)]
+struct GitRewrites {
+    // Discards uncommitted changes to tracked files, e.g. `git reset --hard` or `git restore`
+    discards_changes: bool,
+    // `git clean -f` invocations, which delete untracked files
+    cleans: Vec<SimpleCommand>,
+}
+
+fn git_rewrites(command: &str) -> GitRewrites {
+    let mut rewrites = GitRewrites::default();
+    let Ok(script) = Script::parse(command) else {
+        return rewrites;
+    };
+    for git in script.all_commands().iter().filter_map(git::subcommand) {
+        let has_arg = |names: &[&str]| {
+            git.args()
+                .iter()
+                .any(|arg| names.contains(&arg.text.as_str()))
+        };
+        let forced = git.args().iter().any(|arg| {
+            arg.text == "--force"
+                || (arg.text.starts_with('-')
+                    && !arg.text.starts_with("--")
+                    && arg.text.contains('f'))
+        });
+        match git.program() {
+            Some("reset") if has_arg(&["--hard", "--merge", "--keep"]) => {
+                rewrites.discards_changes = true
+            }
+            // `git checkout -b` and `git restore --staged` leave the worktree alone
+            Some("checkout") if forced || has_arg(&["--", "."]) => rewrites.discards_changes = true,
+            Some("restore") if !has_arg(&["--staged", "-S"]) || has_arg(&["--worktree", "-W"]) => {
+                rewrites.discards_changes = true
+            }
+            Some("switch") if forced || has_arg(&["--discard-changes"]) => {
+                rewrites.discards_changes = true
+            }
+            Some("clean") if forced => rewrites.cleans.push(git),
+            _ => {}
+        }
+    }
+    rewrites
+}
+
+// The untracked files and directories under `cwd` that a `git clean` would delete
+fn untracked_paths(clean: &SimpleCommand, cwd: &Path) -> Vec<PathBuf> {
+    let has_flag = |flag: char| {
+        clean.args().iter().any(|arg| {
+            arg.text.starts_with('-') && !arg.text.starts_with("--") && arg.text.contains(flag)
+        })
+    };
+    let mut args = vec!["ls-files", "--others", "--directory"];
+    if has_flag('X') {
+        args.extend(["--ignored", "--exclude-standard"]);
+    } else if !has_flag('x') {
+        args.push("--exclude-standard");
+    }
+    git::run(cwd, &args)
+        .unwrap_or_default()
+        .lines()
+        .map(|path| cwd.join(path.trim_end_matches('/')))
+        .collect()
+}
+
+// Total size of the files below a path, not following symlinks
+fn disk_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| disk_size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or_default()
+}
+
+// Copies a file, symlink or directory tree
+fn copy_path(source: &Path, target: &Path) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(source)?;
+    if metadata.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(source)?, target)
+    } else if metadata.is_dir() {
+        fs::create_dir(target)?;
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &target.join(entry.file_name()))?;
+        }
+        fs::set_permissions(target, metadata.permissions())
+    } else {
+        fs::copy(source, target).map(|_| ())
+    }
+}
+
+// Saves what a command is about to change so that `aia undo` can restore it.
+// Returns None if the command is not expected to change anything worth saving.
+pub fn take(command: &str, shell: ShellKind, level: RiskLevel) -> Result<Option<Snapshot>> {
+    // The paths are inferred with the bash parser, which would misread other shells
+    if !shell.is_posix() {
+        return Ok(None);
+    }
+    let cwd = std::env::current_dir().context("Failed to get current working directory")?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut snapshot = Snapshot {
+        timestamp: timestamp.as_secs(),
+        cwd: cwd.clone(),
+        command: command.to_string(),
+        paths: Vec::new(),
+        git_stash: None,
+    };
+
+    let mut paths = modified_paths(command, &cwd);
+    // Commands like `git reset --hard` change files the parser cannot tell. Inside a repository
+    // the changes they discard are stashed away, and the files `git clean` deletes are copied.
+    let rewrites = if level >= RiskLevel::Medium {
+        git_rewrites(command)
+    } else {
+        GitRewrites::default()
+    };
+    for clean in &rewrites.cleans {
+        for path in untracked_paths(clean, &cwd) {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    if paths.is_empty() && !rewrites.discards_changes {
+        return Ok(None);
+    }
+
+    let size = paths.iter().map(|path| disk_size(path)).sum::<u64>();
+    if size > SNAPSHOT_LIMIT {
+        return Err(anyhow!(
+            "The paths the command changes take {} MB, more than the {} MB kept for undo",
+            size / 1024 / 1024,
+            SNAPSHOT_LIMIT / 1024 / 1024
+        ));
+    }
+    snapshot.paths = paths
+        .into_iter()
+        .map(|path| SavedPath {
+            existed: fs::symlink_metadata(&path).is_ok(),
+            path,
+        })
+        .collect();
+
+    if rewrites.discards_changes
+        && let Some(stash) = git::run(&cwd, &["stash", "create"]).filter(|stash| !stash.is_empty())
+    {
+        // Stored stashes are listed by `git stash list` and kept from garbage collection
+        git::run(
+            &cwd,
+            &["stash", "store", "-m", &format!("aia: {}", command), &stash],
+        )
+        .context("Failed to store git stash")?;
+        snapshot.git_stash = Some(stash);
+    }
+    if snapshot.paths.is_empty() && snapshot.git_stash.is_none() {
+        return Ok(None);
+    }
+
+    let dir = get_undo_dir()?.join(format!("{:020}", timestamp.as_millis()));
+    let files_dir = dir.join("files");
+    fs::create_dir_all(&files_dir).context("Failed to create undo directory")?;
+    for (index, saved) in snapshot.paths.iter().enumerate() {
+        if saved.existed {
+            copy_path(&saved.path, &files_dir.join(index.to_string()))
+                .with_context(|| format!("Failed to save {}", saved.path.display()))?;
+        }
+    }
+    let json = serde_json::to_string_pretty(&snapshot).context("Failed to serialize snapshot")?;
+    fs::write(dir.join("snapshot.json"), json).context("Failed to write snapshot")?;
+
+    let dirs = snapshot_dirs()?;
+    for old in &dirs[..dirs.len().saturating_sub(MAX_SNAPSHOTS)] {
+        let _ = fs::remove_dir_all(old);
+    }
+    Ok(Some(snapshot))
+}
+
+// Restores the most recent snapshot and removes it
+pub fn undo() -> Result<Snapshot> {
+    let dir = snapshot_dirs()?
+        .pop()
+        .context("Nothing to undo, no command run by aia was saved")?;
+    let json = fs::read_to_string(dir.join("snapshot.json")).context("Failed to read snapshot")?;
+    let snapshot: Snapshot = serde_json::from_str(&json).context("Failed to parse snapshot")?;
+
+    // The stash is applied first, so that nothing is restored if git refuses to apply it
+    if let Some(stash) = &snapshot.git_stash {
+        if git::run(&snapshot.cwd, &["stash", "apply", stash]).is_none() {
+            // The stash stays in `git stash list`; a snapshot without it no longer blocks
+            // older ones, and any copied paths are restored by the next `aia undo`
+            let remaining = Snapshot {
+                git_stash: None,
+                ..snapshot.clone()
+            };
+            if remaining.paths.is_empty() {
+                fs::remove_dir_all(&dir).context("Failed to remove snapshot")?;
+            } else {
+                let json = serde_json::to_string_pretty(&remaining)
+                    .context("Failed to serialize snapshot")?;
+                fs::write(dir.join("snapshot.json"), json).context("Failed to write snapshot")?;
+            }
+            return Err(anyhow!(
+                "Failed to apply the saved changes in {}, commit or stash the current changes and run `git stash apply {}`",
+                snapshot.cwd.display(),
+                stash
+            ));
+        }
+        let stashes =
+            git::run(&snapshot.cwd, &["stash", "list", "--format=%H"]).unwrap_or_default();
+        if let Some(index) = stashes.lines().position(|line| line == stash) {
+            git::run(
+                &snapshot.cwd,
+                &["stash", "drop", &format!("stash@{{{}}}", index)],
+            );
+        }
+    }
+
+    // Later paths may lie inside earlier ones, so they are restored first
+    for (index, saved) in snapshot.paths.iter().enumerate().rev() {
+        let context = || format!("Failed to restore {}", saved.path.display());
+        fsutil::remove_path(&saved.path).with_context(context)?;
+        if saved.existed {
+            copy_path(&dir.join("files").join(index.to_string()), &saved.path)
+                .with_context(context)?;
+        }
+    }
+
+    fs::remove_dir_all(&dir).context("Failed to remove snapshot")?;
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_shells_are_not_snapshotted() {
+        assert!(
+            take("ls | where size > 10mb", ShellKind::Nu, RiskLevel::Medium)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn only_worktree_rewrites_are_stashed() {
+        for command in [
+            "git reset --hard HEAD~1",
+            "git checkout -- src",
+            "git checkout .",
+            "git checkout -f main",
+            "git restore .",
+            "git restore --staged --worktree src",
+            "git -C repo switch -f main",
+        ] {
+            assert!(git_rewrites(command).discards_changes, "{}", command);
+        }
+        for command in [
+            "git commit -am wip",
+            "git checkout -b feature",
+            "git checkout main",
+            "git restore --staged src",
+            "git push",
+            "git reset HEAD~1",
+            "git clean -n",
+            "docker ps",
+            "kill 1234",
+        ] {
+            let rewrites = git_rewrites(command);
+            assert!(!rewrites.discards_changes, "{}", command);
+            assert!(rewrites.cleans.is_empty(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn forced_cleans_save_untracked_files() {
+        let rewrites = git_rewrites("git reset --hard && git clean -fd");
+        assert!(rewrites.discards_changes);
+        assert_eq!(rewrites.cleans.len(), 1);
+        assert_eq!(rewrites.cleans[0].program(), Some("clean"));
+    }
+}